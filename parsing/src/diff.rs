@@ -0,0 +1,229 @@
+//! Word level diffing of message revisions
+
+use std::mem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    Equal(&'a str),
+    Insert(&'a str),
+    Delete(&'a str),
+}
+
+/// Split text into words and runs of whitespace, returned as byte ranges covering the input
+fn tokenize(text: &str) -> Vec<(usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_whitespace = None;
+    for (i, c) in text.char_indices() {
+        let whitespace = c.is_whitespace();
+        if in_whitespace.map_or(false, |prev| prev != whitespace) {
+            tokens.push((start, i));
+            start = i;
+        }
+        in_whitespace = Some(whitespace);
+    }
+    if start < text.len() {
+        tokens.push((start, text.len()));
+    }
+    tokens
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Lengths of the longest common subsequences of `old` and each prefix of `new`, or of each
+/// suffix when `reversed`, only two rows are kept
+fn lcs_row(old: &[&str], new: &[&str], reversed: bool) -> Vec<usize> {
+    let m = new.len();
+    let mut previous = vec![0; m + 1];
+    let mut row = vec![0; m + 1];
+    for i in 0..old.len() {
+        let a = if reversed {
+            old[old.len() - 1 - i]
+        } else {
+            old[i]
+        };
+        for j in 0..m {
+            let b = if reversed { new[m - 1 - j] } else { new[j] };
+            row[j + 1] = if a == b {
+                previous[j] + 1
+            } else {
+                previous[j + 1].max(row[j])
+            };
+        }
+        mem::swap(&mut previous, &mut row);
+    }
+    previous
+}
+
+/// Align `old` with `new` along a longest common subsequence using Hirschberg's algorithm, in
+/// linear space. Tokens are pushed with their index offset by `start`
+fn align(old: &[&str], new: &[&str], start: (usize, usize), edits: &mut Vec<(Kind, usize)>) {
+    let (i0, j0) = start;
+    if old.is_empty() {
+        edits.extend((0..new.len()).map(|j| (Kind::Insert, j0 + j)));
+        return;
+    }
+    if new.is_empty() {
+        edits.extend((0..old.len()).map(|i| (Kind::Delete, i0 + i)));
+        return;
+    }
+    if old.len() == 1 {
+        match new.iter().position(|word| *word == old[0]) {
+            Some(k) => {
+                edits.extend((0..k).map(|j| (Kind::Insert, j0 + j)));
+                edits.push((Kind::Equal, i0));
+                edits.extend((k + 1..new.len()).map(|j| (Kind::Insert, j0 + j)));
+            },
+            None => {
+                edits.push((Kind::Delete, i0));
+                edits.extend((0..new.len()).map(|j| (Kind::Insert, j0 + j)));
+            },
+        }
+        return;
+    }
+
+    let mid = old.len() / 2;
+    let left = lcs_row(&old[..mid], new, false);
+    let right = lcs_row(&old[mid..], new, true);
+    let mut split = 0;
+    for j in 1..=new.len() {
+        if left[j] + right[new.len() - j] > left[split] + right[new.len() - split] {
+            split = j;
+        }
+    }
+    align(&old[..mid], &new[..split], (i0, j0), edits);
+    align(&old[mid..], &new[split..], (i0 + mid, j0 + split), edits);
+}
+
+/// Diff two strings word by word, adjacent changes of the same kind are merged
+pub fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let old_words = old_tokens
+        .iter()
+        .map(|&(start, end)| &old[start..end])
+        .collect::<Vec<_>>();
+    let new_words = new_tokens
+        .iter()
+        .map(|&(start, end)| &new[start..end])
+        .collect::<Vec<_>>();
+
+    // Edits usually touch a small part of a message, so the common ends are skipped
+    let prefix = old_words
+        .iter()
+        .zip(&new_words)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_words[prefix..]
+        .iter()
+        .rev()
+        .zip(new_words[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (n, m) = (old_words.len(), new_words.len());
+
+    let mut edits = (0..prefix).map(|i| (Kind::Equal, i)).collect::<Vec<_>>();
+    align(
+        &old_words[prefix..n - suffix],
+        &new_words[prefix..m - suffix],
+        (prefix, prefix),
+        &mut edits,
+    );
+    edits.extend((n - suffix..n).map(|i| (Kind::Equal, i)));
+
+    // Consecutive tokens of the same kind are always adjacent in their source, so runs can be
+    // stored as a single range
+    let mut runs: Vec<(Kind, usize, usize)> = Vec::new();
+    for (kind, index) in edits {
+        let (start, end) = match kind {
+            Kind::Equal | Kind::Delete => old_tokens[index],
+            Kind::Insert => new_tokens[index],
+        };
+        match runs.last_mut() {
+            Some(last) if last.0 == kind => last.2 = end,
+            _ => runs.push((kind, start, end)),
+        }
+    }
+
+    runs.into_iter()
+        .map(|(kind, start, end)| match kind {
+            Kind::Equal => Change::Equal(&old[start..end]),
+            Kind::Insert => Change::Insert(&new[start..end]),
+            Kind::Delete => Change::Delete(&old[start..end]),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_word() {
+        assert_eq!(
+            diff_words("a b c", "a x c"),
+            vec![
+                Change::Equal("a "),
+                Change::Delete("b"),
+                Change::Insert("x"),
+                Change::Equal(" c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn added_and_removed_text() {
+        assert_eq!(
+            diff_words("", "hello world"),
+            vec![Change::Insert("hello world")]
+        );
+        assert_eq!(
+            diff_words("hello world", ""),
+            vec![Change::Delete("hello world")]
+        );
+        assert_eq!(diff_words("same", "same"), vec![Change::Equal("same")]);
+    }
+
+    #[test]
+    fn several_changes() {
+        assert_eq!(
+            diff_words("the quick brown fox", "the slow brown dog jumps"),
+            vec![
+                Change::Equal("the "),
+                Change::Delete("quick"),
+                Change::Insert("slow"),
+                Change::Equal(" brown "),
+                Change::Delete("fox"),
+                Change::Insert("dog jumps"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_reconstructs_both_revisions() {
+        let old = "one two three two one four";
+        let new = "two one three four four one";
+        let changes = diff_words(old, new);
+        let before = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Equal(text) | Change::Delete(text) => Some(*text),
+                Change::Insert(_) => None,
+            })
+            .collect::<String>();
+        let after = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Equal(text) | Change::Insert(text) => Some(*text),
+                Change::Delete(_) => None,
+            })
+            .collect::<String>();
+        assert_eq!(before, old);
+        assert_eq!(after, new);
+    }
+}
@@ -0,0 +1,273 @@
+//! A small tokenizer based syntax highlighter for fenced code blocks
+
+/// The class of a highlighted token, used to pick a color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Text,
+    Keyword,
+    Type,
+    Literal,
+    String,
+    Number,
+    Comment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+}
+
+struct Language {
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    /// Only treat the line comment as a comment if it starts a word (`#` in shell)
+    line_comment_at_word_start: bool,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// String delimiters, longer delimiters must come first
+    quotes: &'static [&'static str],
+    /// Identifiers starting with an uppercase letter are types
+    capitalized_types: bool,
+    /// `'` starts either a char literal or a lifetime
+    char_literals: bool,
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "static", "struct", "super", "trait", "type", "union",
+        "unsafe", "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8",
+        "u16", "u32", "u64", "u128", "usize",
+    ],
+    literals: &["true", "false", "Some", "None", "Ok", "Err"],
+    line_comment: Some("//"),
+    line_comment_at_word_start: false,
+    block_comment: Some(("/*", "*/")),
+    quotes: &["\""],
+    capitalized_types: true,
+    char_literals: true,
+};
+
+const PYTHON: Language = Language {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+        "yield",
+    ],
+    types: &[
+        "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+    ],
+    literals: &["True", "False", "None", "self"],
+    line_comment: Some("#"),
+    line_comment_at_word_start: false,
+    block_comment: None,
+    quotes: &["\"\"\"", "'''", "\"", "'"],
+    capitalized_types: true,
+    char_literals: false,
+};
+
+const JSON: Language = Language {
+    keywords: &[],
+    types: &[],
+    literals: &["true", "false", "null"],
+    line_comment: None,
+    line_comment_at_word_start: false,
+    block_comment: None,
+    quotes: &["\""],
+    capitalized_types: false,
+    char_literals: false,
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "select", "then", "until", "while",
+    ],
+    types: &[],
+    literals: &["true", "false"],
+    line_comment: Some("#"),
+    line_comment_at_word_start: true,
+    block_comment: None,
+    quotes: &["\"", "'"],
+    capitalized_types: false,
+    char_literals: false,
+};
+
+fn language(name: &str) -> Option<&'static Language> {
+    match name.trim().to_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "python" | "python3" | "py" => Some(&PYTHON),
+        "json" => Some(&JSON),
+        "sh" | "bash" | "zsh" | "shell" | "console" => Some(&SHELL),
+        _ => None,
+    }
+}
+
+/// Split `code` into highlighted tokens, returns `None` if the language is not known
+///
+/// Adjacent tokens of the same kind are merged, and the concatenation of all tokens is `code`
+pub fn highlight<'a>(language_name: &str, code: &'a str) -> Option<Vec<Token<'a>>> {
+    let lang = language(language_name)?;
+
+    let mut spans: Vec<(TokenKind, usize, usize)> = Vec::new();
+    let mut pos = 0;
+    while pos < code.len() {
+        let (kind, len) = next_token(lang, code, pos);
+        match spans.last_mut() {
+            Some(last) if last.0 == kind => last.2 = pos + len,
+            _ => spans.push((kind, pos, pos + len)),
+        }
+        pos += len;
+    }
+
+    Some(
+        spans
+            .into_iter()
+            .map(|(kind, start, end)| Token {
+                kind,
+                text: &code[start..end],
+            })
+            .collect(),
+    )
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Classify the token starting at `pos`, returning its kind and length in bytes
+fn next_token(lang: &Language, code: &str, pos: usize) -> (TokenKind, usize) {
+    let rest = &code[pos..];
+    let first = match rest.chars().next() {
+        Some(c) => c,
+        None => return (TokenKind::Text, 0),
+    };
+
+    if let Some(prefix) = lang.line_comment {
+        let at_word_start = code[..pos]
+            .chars()
+            .next_back()
+            .map_or(true, char::is_whitespace);
+        if rest.starts_with(prefix) && (!lang.line_comment_at_word_start || at_word_start) {
+            return (
+                TokenKind::Comment,
+                rest.find('\n').unwrap_or_else(|| rest.len()),
+            );
+        }
+    }
+
+    if let Some((start, end)) = lang.block_comment {
+        if rest.starts_with(start) {
+            let len = rest[start.len()..]
+                .find(end)
+                .map_or(rest.len(), |i| start.len() + i + end.len());
+            return (TokenKind::Comment, len);
+        }
+    }
+
+    if lang.char_literals && first == '\'' {
+        return (char_literal_or_lifetime(rest), char_literal_len(rest));
+    }
+
+    for quote in lang.quotes {
+        if rest.starts_with(quote) {
+            return (TokenKind::String, string_len(rest, quote));
+        }
+    }
+
+    if first.is_ascii_digit() {
+        return (TokenKind::Number, number_len(rest));
+    }
+
+    if is_ident_char(first) {
+        let len = rest
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or_else(|| rest.len());
+        let word = &rest[..len];
+        let kind = if lang.keywords.contains(&word) {
+            TokenKind::Keyword
+        } else if lang.literals.contains(&word) {
+            TokenKind::Literal
+        } else if lang.types.contains(&word) || (lang.capitalized_types && first.is_uppercase()) {
+            TokenKind::Type
+        } else {
+            TokenKind::Text
+        };
+        return (kind, len);
+    }
+
+    if first.is_whitespace() {
+        let len = rest
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or_else(|| rest.len());
+        return (TokenKind::Text, len);
+    }
+
+    (TokenKind::Text, first.len_utf8())
+}
+
+/// Length of a string starting with `quote`, unterminated strings run to the end of the code
+fn string_len(rest: &str, quote: &str) -> usize {
+    let mut chars = rest[quote.len()..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if rest[quote.len() + i..].starts_with(quote) {
+            return quote.len() + i + quote.len();
+        }
+    }
+    rest.len()
+}
+
+fn is_char_literal(rest: &str) -> bool {
+    let mut chars = rest.chars().skip(1);
+    match (chars.next(), chars.next()) {
+        (Some('\\'), _) => true,
+        (Some(_), Some('\'')) => true,
+        _ => false,
+    }
+}
+
+fn char_literal_or_lifetime(rest: &str) -> TokenKind {
+    if is_char_literal(rest) {
+        TokenKind::String
+    } else {
+        TokenKind::Text
+    }
+}
+
+/// Length of a char literal (`'a'`, `'\n'`) or a lifetime (`'static`)
+fn char_literal_len(rest: &str) -> usize {
+    if is_char_literal(rest) {
+        string_len(rest, "'")
+    } else {
+        1 + rest[1..]
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or_else(|| rest.len() - 1)
+    }
+}
+
+fn number_len(rest: &str) -> usize {
+    let mut len = 0;
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        let continues = if c == '.' {
+            // Don't swallow ranges (`0..10`) or method calls (`1.max(2)`)
+            chars.peek().map_or(false, char::is_ascii_digit)
+        } else {
+            is_ident_char(c)
+        };
+        if !continues {
+            break;
+        }
+        len += c.len_utf8();
+    }
+    len
+}
@@ -1,6 +1,11 @@
 use lazy_static::lazy_static;
 pub use simple_ast::MarkdownNode;
 use simple_ast::{regex::Regex, Parser, Rule, Styled};
+use unicode_segmentation::UnicodeSegmentation;
+
+pub mod diff;
+pub mod highlight;
+pub mod split;
 
 pub fn parse_markdown(str: &str) -> Styled<MarkdownNode> {
     use simple_ast::markdown_rules::*;
@@ -26,54 +31,856 @@ pub fn weechat_arg_strip(str: &str) -> String {
 }
 
 lazy_static! {
-    static ref LINE_SUB_REGEX: Regex =
-        Regex::new(r"^(\d+)?s/(.*?(?<!\\))/(.*?(?<!\\))(?:/|$)(\w+)?").unwrap();
-    static ref REACTION_REGEX: Regex = Regex::new(r"^(\d+)?([\+\-])(.).*$").unwrap();
+    static ref LINE_SUB_REGEX: Regex = Regex::new(r"^(\d+)?s/(.*)$").unwrap();
+    static ref REACTION_REGEX: Regex = Regex::new(r"^(\d+)?([\+\-])(.+)$").unwrap();
+    static ref CUSTOM_EMOJI_REGEX: Regex = Regex::new(r"^<(a)?:(\w+):(\d+)>$").unwrap();
+    static ref SHORTCODE_REGEX: Regex = Regex::new(r"^:(\w+):$").unwrap();
+    static ref REPLY_REGEX: Regex = Regex::new(r"^>(\d+) (.+)$").unwrap();
+}
+
+/// Numbers with at least this many digits are message ids rather than indices
+const MIN_ID_DIGITS: usize = 15;
+
+/// The message an edit or reaction applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageTarget {
+    /// The nth most recent message (starting at 1)
+    Nth(usize),
+    /// A message id
+    Id(u64),
+}
+
+impl MessageTarget {
+    /// Parse the number before an edit or reaction, numbers with `MIN_ID_DIGITS` or more are ids
+    /// and no number is the most recent message
+    pub fn parse(digits: Option<&str>) -> Option<MessageTarget> {
+        match digits {
+            None => Some(MessageTarget::Nth(1)),
+            Some(digits) if digits.len() >= MIN_ID_DIGITS => {
+                digits.parse().ok().map(MessageTarget::Id)
+            },
+            Some(digits) => digits.parse().ok().map(MessageTarget::Nth),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum LineEdit<'a> {
+    /// `s/old/new/options`, `old` and `new` have escaped delimiters (`\/`) unescaped
     Sub {
-        line: usize,
-        old: &'a str,
-        new: &'a str,
-        options: Option<&'a str>,
+        target: MessageTarget,
+        old: String,
+        new: String,
+        options: &'a str,
     },
     Delete {
-        line: usize,
+        target: MessageTarget,
     },
 }
 
 #[derive(Debug)]
 pub struct Reaction<'a> {
     pub add: bool,
-    pub unicode: &'a str,
+    pub emoji: ReactionEmoji<'a>,
+    pub target: MessageTarget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReactionEmoji<'a> {
+    /// A single grapheme cluster, such as `👍` or `🏳️‍🌈`
+    Unicode(&'a str),
+    /// `:name:`, to be resolved against the guild's emojis
+    Shortcode(&'a str),
+    /// `<:name:id>` or `<a:name:id>`
+    Custom {
+        animated: bool,
+        name: &'a str,
+        id: u64,
+    },
+}
+
+/// `>N text`, a reply to the Nth most recent message
+#[derive(Debug)]
+pub struct Reply<'a> {
     pub line: usize,
+    pub text: &'a str,
 }
 
 pub fn parse_line_edit(input: &str) -> Option<LineEdit> {
     let caps = LINE_SUB_REGEX.captures(input)?;
 
-    let line = caps.at(1).and_then(|l| l.parse().ok()).unwrap_or(1);
-    let old = caps.at(2)?;
-    let new = caps.at(3)?;
+    let target = MessageTarget::parse(caps.at(1))?;
+    let body = caps.at(2)?;
+
+    let (old, rest) = split_unescaped(body);
+    let rest = rest?;
+    let (new, options) = split_unescaped(rest);
 
     if old.is_empty() && new.is_empty() {
-        Some(LineEdit::Delete { line })
+        Some(LineEdit::Delete { target })
     } else {
         Some(LineEdit::Sub {
-            line,
+            target,
             old,
             new,
-            options: caps.at(4),
+            options: options.unwrap_or("").trim(),
         })
     }
 }
 
+/// Split at the first unescaped `/`, unescaping `\/` in the first part
+///
+/// Returns the whole input as the first part if there is no delimiter
+fn split_unescaped(input: &str) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => return (part, Some(&input[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '/')) => part.push('/'),
+                // Keep other escapes (including `\\`) for the regex
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                },
+                None => part.push('\\'),
+            },
+            c => part.push(c),
+        }
+    }
+    (part, None)
+}
+
 pub fn parse_reaction(input: &str) -> Option<Reaction> {
     let caps = REACTION_REGEX.captures(input)?;
-    let line = caps.at(1).and_then(|l| l.parse().ok()).unwrap_or(1);
-    let unicode_opt = caps.at(3);
+    let target = MessageTarget::parse(caps.at(1))?;
     let add = caps.at(2) == Some("+");
-    unicode_opt.map(|unicode| Reaction { add, unicode, line })
+    let emoji = parse_reaction_emoji(caps.at(3)?)?;
+    Some(Reaction { add, emoji, target })
+}
+
+pub fn parse_reply(input: &str) -> Option<Reply> {
+    let caps = REPLY_REGEX.captures(input)?;
+    let line = caps.at(1)?.parse().ok().filter(|&line| line > 0)?;
+    let text = caps.at(2)?.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(Reply { line, text })
+}
+
+/// Parse the emoji of a reaction, the whole input must be a single emoji
+fn parse_reaction_emoji(input: &str) -> Option<ReactionEmoji> {
+    let input = input.trim_end();
+    if let Some(caps) = CUSTOM_EMOJI_REGEX.captures(input) {
+        return Some(ReactionEmoji::Custom {
+            animated: caps.at(1).is_some(),
+            name: caps.at(2)?,
+            id: caps.at(3)?.parse().ok()?,
+        });
+    }
+
+    if let Some(caps) = SHORTCODE_REGEX.captures(input) {
+        return Some(ReactionEmoji::Shortcode(caps.at(1)?));
+    }
+
+    let mut graphemes = input.graphemes(true);
+    match (graphemes.next(), graphemes.next()) {
+        (Some(emoji), None) if emoji.chars().any(is_emoji_char) => {
+            Some(ReactionEmoji::Unicode(emoji))
+        },
+        _ => None,
+    }
+}
+
+/// Whether a character is only used in emoji, so inputs like `-a` aren't taken as reactions
+fn is_emoji_char(c: char) -> bool {
+    matches!(c as u32,
+        0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x20E3 | 0x24C2 | 0x2934 | 0x2935
+        | 0x3030 | 0x303D | 0x3297 | 0x3299
+        | 0x2190..=0x21FF
+        | 0x2300..=0x23FF
+        | 0x25AA..=0x25FE
+        | 0x2600..=0x27BF
+        | 0x2B00..=0x2BFF
+        | 0x1F000..=0x1FAFF
+    )
+}
+
+/// How a Discord dynamic timestamp (`<t:UNIX:STYLE>`) should be displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampStyle {
+    /// `t`, 16:20
+    ShortTime,
+    /// `T`, 16:20:30
+    LongTime,
+    /// `d`, 20/04/2021
+    ShortDate,
+    /// `D`, 20 April 2021
+    LongDate,
+    /// `f` (default), 20 April 2021 16:20
+    ShortDateTime,
+    /// `F`, Tuesday, 20 April 2021 16:20
+    LongDateTime,
+    /// `R`, 2 months ago
+    Relative,
+}
+
+impl TimestampStyle {
+    fn from_flag(flag: &str) -> Option<TimestampStyle> {
+        use TimestampStyle::*;
+        Some(match flag {
+            "t" => ShortTime,
+            "T" => LongTime,
+            "d" => ShortDate,
+            "D" => LongDate,
+            "f" => ShortDateTime,
+            "F" => LongDateTime,
+            "R" => Relative,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub unix: i64,
+    pub style: TimestampStyle,
+}
+
+/// Inline Discord syntax that is not handled by `simple_ast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inline<'a> {
+    Text(&'a str),
+    Timestamp(Timestamp),
+    /// `[label](url)`
+    MaskedLink {
+        label: &'a str,
+        url: &'a str,
+    },
+}
+
+/// Split plain text (the contents of text nodes) into inline elements
+pub fn parse_inline(text: &str) -> Vec<Inline> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        match next_inline(rest) {
+            Some((start, len, inline)) => {
+                if start > 0 {
+                    out.push(Inline::Text(&rest[..start]));
+                }
+                out.push(inline);
+                rest = &rest[start + len..];
+            },
+            None => {
+                out.push(Inline::Text(rest));
+                break;
+            },
+        }
+    }
+    out
+}
+
+/// Find the first inline element, returning its start, length and value
+fn next_inline(text: &str) -> Option<(usize, usize, Inline)> {
+    text.char_indices().find_map(|(start, _)| {
+        let rest = &text[start..];
+        parse_timestamp(rest)
+            .map(|(len, ts)| (len, Inline::Timestamp(ts)))
+            .or_else(|| parse_masked_link(rest))
+            .map(|(len, inline)| (start, len, inline))
+    })
+}
+
+fn parse_timestamp(text: &str) -> Option<(usize, Timestamp)> {
+    let rest = text.strip_prefix("<t:")?;
+    let end = rest.find('>')?;
+    let inner = &rest[..end];
+
+    let (unix, style) = match inner.find(':') {
+        Some(sep) => (&inner[..sep], TimestampStyle::from_flag(&inner[sep + 1..])?),
+        None => (inner, TimestampStyle::ShortDateTime),
+    };
+    if unix.starts_with('+') {
+        return None;
+    }
+    let unix = unix.parse().ok()?;
+
+    Some(("<t:".len() + end + ">".len(), Timestamp { unix, style }))
+}
+
+fn parse_masked_link(text: &str) -> Option<(usize, Inline)> {
+    let rest = text.strip_prefix('[')?;
+    let label_end = rest.find(']')?;
+    let label = &rest[..label_end];
+    if label.trim().is_empty() || label.contains(|c| c == '[' || c == '\n') {
+        return None;
+    }
+
+    let rest = rest[label_end..].strip_prefix("](")?;
+    let url_end = rest.find(')')?;
+    let url = &rest[..url_end];
+    // `<url>` suppresses the embed in the official client
+    let url = url
+        .strip_prefix('<')
+        .and_then(|url| url.strip_suffix('>'))
+        .unwrap_or(url);
+    if !(url.starts_with("https://") || url.starts_with("http://"))
+        || url.contains(char::is_whitespace)
+    {
+        return None;
+    }
+
+    let len = "[".len() + label_end + "](".len() + url_end + ")".len();
+    Some((len, Inline::MaskedLink { label, url }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMarker {
+    /// `-` or `*`
+    Bullet,
+    /// `1.`
+    Numbered(usize),
+}
+
+/// Line based Discord syntax that is not handled by `simple_ast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Block<'a> {
+    /// One or more lines without any block syntax, to be parsed with `parse_markdown`
+    Markdown(&'a str),
+    /// `#`, `##` or `###`
+    Heading { level: usize, text: &'a str },
+    /// `-#`
+    Subtext(&'a str),
+    /// `- item` or `1. item`, indented items are nested
+    ListItem {
+        depth: usize,
+        marker: ListMarker,
+        text: &'a str,
+    },
+}
+
+/// Split a message into headings, subtext, list items and regular markdown
+///
+/// Lines inside code blocks and multi-line block quotes are never treated as block syntax
+pub fn parse_blocks(str: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut in_code = false;
+    let mut in_quote = false;
+    // Start of the current run of regular markdown lines
+    let mut run_start = 0;
+    let mut line_start = 0;
+
+    for line in str.split('\n') {
+        let line_end = line_start + line.len();
+        let block = if in_code || in_quote {
+            None
+        } else {
+            parse_block_line(line)
+        };
+
+        if let Some(block) = block {
+            if run_start < line_start {
+                blocks.push(Block::Markdown(&str[run_start..line_start - 1]));
+            }
+            blocks.push(block);
+            run_start = (line_end + 1).min(str.len());
+        } else {
+            if line.matches("```").count() % 2 == 1 {
+                in_code = !in_code;
+            }
+            if !in_code && line.starts_with(">>> ") {
+                in_quote = true;
+            }
+        }
+        line_start = line_end + 1;
+    }
+    if run_start < str.len() || blocks.is_empty() {
+        blocks.push(Block::Markdown(&str[run_start..]));
+    }
+
+    blocks
+}
+
+fn parse_block_line(line: &str) -> Option<Block> {
+    if let Some(text) = line.strip_prefix("-# ") {
+        return Some(Block::Subtext(text.trim()));
+    }
+
+    let hashes = line.find(|c: char| c != '#').unwrap_or_else(|| line.len());
+    if (1..=3).contains(&hashes) {
+        if let Some(text) = line[hashes..].strip_prefix(' ') {
+            if !text.trim().is_empty() {
+                return Some(Block::Heading {
+                    level: hashes,
+                    text: text.trim(),
+                });
+            }
+        }
+    }
+
+    let content = line.trim_start_matches(' ');
+    let depth = (line.len() - content.len() + 1) / 2;
+    let (marker, text) = if let Some(text) = content
+        .strip_prefix("- ")
+        .or_else(|| content.strip_prefix("* "))
+    {
+        (ListMarker::Bullet, text)
+    } else {
+        let digits = content
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or_else(|| content.len());
+        let text = content[digits..].strip_prefix(". ")?;
+        (ListMarker::Numbered(content[..digits].parse().ok()?), text)
+    };
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some(Block::ListItem {
+        depth,
+        marker,
+        text: text.trim_end(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputStyle {
+    Bold,
+    Italic,
+    Underline,
+    Reverse,
+}
+
+impl InputStyle {
+    fn markdown(self) -> &'static str {
+        match self {
+            InputStyle::Bold => "**",
+            InputStyle::Italic => "*",
+            InputStyle::Underline => "__",
+            // Reverse video has no Discord equivalent, a spoiler is the closest
+            InputStyle::Reverse => "||",
+        }
+    }
+}
+
+/// Convert weechat input formatting codes (ctrl-c b/i/_/v/c/o) into Discord markdown
+///
+/// Colors are stripped as Discord has no way to display them. If `escape` is set, markdown
+/// characters in the text itself are escaped so only the weechat formatting is applied.
+pub fn weechat_to_discord(input: &str, escape: bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    // Styles that are enabled at the current position, in the order they were enabled
+    let mut active: Vec<InputStyle> = Vec::new();
+    // Styles that have been opened in the output
+    let mut open: Vec<InputStyle> = Vec::new();
+
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let toggled = match c {
+            '\x02' => Some(InputStyle::Bold),
+            '\x1D' => Some(InputStyle::Italic),
+            '\x1F' => Some(InputStyle::Underline),
+            '\x16' => Some(InputStyle::Reverse),
+            _ => None,
+        };
+        if let Some(style) = toggled {
+            if let Some(pos) = active.iter().position(|s| *s == style) {
+                active.remove(pos);
+            } else {
+                active.push(style);
+            }
+            continue;
+        }
+
+        match c {
+            // Reset
+            '\x0F' => active.clear(),
+            // Color: ^Cfg[,bg] with up to two digits each
+            '\x03' => {
+                skip_digits(&mut chars);
+                if chars.peek().map(|(_, c)| *c) == Some(',') {
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    if lookahead.peek().map_or(false, |(_, c)| c.is_ascii_digit()) {
+                        chars.next();
+                        skip_digits(&mut chars);
+                    }
+                }
+            },
+            _ => {
+                sync_styles(&mut out, &mut open, &active);
+                if escape && needs_escape(input, i, c) {
+                    out.push('\\');
+                }
+                out.push(c);
+            },
+        }
+    }
+    sync_styles(&mut out, &mut open, &[]);
+
+    out
+}
+
+fn skip_digits(chars: &mut std::iter::Peekable<std::str::CharIndices>) {
+    for _ in 0..2 {
+        if chars.peek().map_or(false, |(_, c)| c.is_ascii_digit()) {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Close and open markdown styles so that the open styles match the active ones
+///
+/// Markdown must be properly nested, so any style opened after one that has to be closed is also
+/// closed and then reopened
+fn sync_styles(out: &mut String, open: &mut Vec<InputStyle>, active: &[InputStyle]) {
+    let common = open
+        .iter()
+        .zip(active)
+        .take_while(|(open, active)| open == active)
+        .count();
+    while open.len() > common {
+        if let Some(style) = open.pop() {
+            out.push_str(style.markdown());
+        }
+    }
+    for style in &active[common..] {
+        out.push_str(style.markdown());
+        open.push(*style);
+    }
+}
+
+/// Escape every markdown character so the text is displayed literally
+pub fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '_' | '~' | '`' | '|' | '\\' | '>') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn needs_escape(input: &str, i: usize, c: char) -> bool {
+    match c {
+        '*' | '~' | '`' | '|' | '\\' | '>' => true,
+        // Underscores inside words (snake_case, :custom_emoji:, #channel_name) are not markdown
+        '_' => {
+            let before = input[..i].chars().next_back();
+            let after = input[i + 1..].chars().next();
+            !(before.map_or(false, char::is_alphanumeric)
+                && after.map_or(false, char::is_alphanumeric))
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_with_style() {
+        assert_eq!(
+            parse_timestamp("<t:1618953630:R> later"),
+            Some((
+                16,
+                Timestamp {
+                    unix: 1618953630,
+                    style: TimestampStyle::Relative,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn timestamp_defaults_to_short_date_time() {
+        assert_eq!(
+            parse_timestamp("<t:0>"),
+            Some((
+                5,
+                Timestamp {
+                    unix: 0,
+                    style: TimestampStyle::ShortDateTime,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        assert_eq!(parse_timestamp("<t:123:x>"), None);
+        assert_eq!(parse_timestamp("<t:+123>"), None);
+        assert_eq!(parse_timestamp("<t:abc>"), None);
+        assert_eq!(parse_timestamp("<t:123"), None);
+    }
+
+    #[test]
+    fn inline_timestamp_between_text() {
+        assert_eq!(
+            parse_inline("at <t:60:t>!"),
+            vec![
+                Inline::Text("at "),
+                Inline::Timestamp(Timestamp {
+                    unix: 60,
+                    style: TimestampStyle::ShortTime,
+                }),
+                Inline::Text("!"),
+            ]
+        );
+        assert_eq!(
+            parse_inline("no timestamp"),
+            vec![Inline::Text("no timestamp")]
+        );
+    }
+
+    #[test]
+    fn masked_link() {
+        assert_eq!(
+            parse_masked_link("[docs](https://example.com) rest"),
+            Some((
+                27,
+                Inline::MaskedLink {
+                    label: "docs",
+                    url: "https://example.com",
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn masked_link_with_suppressed_embed() {
+        assert_eq!(
+            parse_masked_link("[x](<https://a.b>)"),
+            Some((
+                18,
+                Inline::MaskedLink {
+                    label: "x",
+                    url: "https://a.b",
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_masked_links() {
+        assert_eq!(parse_masked_link("[x](javascript:alert(1))"), None);
+        assert_eq!(parse_masked_link("[ ](https://a.b)"), None);
+        assert_eq!(parse_masked_link("[x](https://a.b/c d)"), None);
+        assert_eq!(parse_masked_link("[x] (https://a.b)"), None);
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            parse_blocks("# Title\ntext\n- item\n  - nested\n1. one\n-# small"),
+            vec![
+                Block::Heading {
+                    level: 1,
+                    text: "Title",
+                },
+                Block::Markdown("text"),
+                Block::ListItem {
+                    depth: 0,
+                    marker: ListMarker::Bullet,
+                    text: "item",
+                },
+                Block::ListItem {
+                    depth: 1,
+                    marker: ListMarker::Bullet,
+                    text: "nested",
+                },
+                Block::ListItem {
+                    depth: 0,
+                    marker: ListMarker::Numbered(1),
+                    text: "one",
+                },
+                Block::Subtext("small"),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_blocks_in_code_or_quotes() {
+        let code = "```\n# not a heading\n```";
+        assert_eq!(parse_blocks(code), vec![Block::Markdown(code)]);
+        let quote = ">>> quoted\n- not a list";
+        assert_eq!(parse_blocks(quote), vec![Block::Markdown(quote)]);
+    }
+
+    #[test]
+    fn plain_markdown_blocks() {
+        assert_eq!(parse_blocks(""), vec![Block::Markdown("")]);
+        assert_eq!(parse_blocks("#tag"), vec![Block::Markdown("#tag")]);
+        assert_eq!(
+            parse_blocks("#### four"),
+            vec![Block::Markdown("#### four")]
+        );
+    }
+
+    #[test]
+    fn weechat_styles_to_markdown() {
+        assert_eq!(
+            weechat_to_discord("\x02bold\x02 text", false),
+            "**bold** text"
+        );
+        assert_eq!(weechat_to_discord("\x1Fu\x1F \x16v", false), "__u__ ||v||");
+    }
+
+    #[test]
+    fn overlapping_weechat_styles_are_nested() {
+        assert_eq!(
+            weechat_to_discord("\x02a\x1Db\x02c\x1D", false),
+            "**a*b****c*"
+        );
+    }
+
+    #[test]
+    fn weechat_colors_are_stripped() {
+        assert_eq!(
+            weechat_to_discord("\x0304,05red\x0F plain", false),
+            "red plain"
+        );
+        assert_eq!(weechat_to_discord("\x0312,x", false), ",x");
+    }
+
+    #[test]
+    fn weechat_input_escaping() {
+        assert_eq!(
+            weechat_to_discord("*hi* snake_case _x_", true),
+            "\\*hi\\* snake_case \\_x\\_"
+        );
+        assert_eq!(weechat_to_discord("*hi* _x_", false), "*hi* _x_");
+    }
+
+    #[test]
+    fn split_at_unescaped_delimiter() {
+        assert_eq!(
+            split_unescaped("a\\/b/c/g"),
+            ("a/b".to_owned(), Some("c/g"))
+        );
+        assert_eq!(split_unescaped("a\\.b"), ("a\\.b".to_owned(), None));
+        assert_eq!(split_unescaped("end\\"), ("end\\".to_owned(), None));
+        assert_eq!(split_unescaped(""), (String::new(), None));
+    }
+
+    #[test]
+    fn line_substitution() {
+        match parse_line_edit("2s/a\\/b/c/gi") {
+            Some(LineEdit::Sub {
+                target,
+                old,
+                new,
+                options,
+            }) => {
+                assert_eq!(target, MessageTarget::Nth(2));
+                assert_eq!(old, "a/b");
+                assert_eq!(new, "c");
+                assert_eq!(options, "gi");
+            },
+            edit => panic!("unexpected edit {:?}", edit),
+        }
+    }
+
+    #[test]
+    fn line_deletion() {
+        match parse_line_edit("s///") {
+            Some(LineEdit::Delete { target }) => assert_eq!(target, MessageTarget::Nth(1)),
+            edit => panic!("unexpected edit {:?}", edit),
+        }
+        match parse_line_edit("123456789012345678s///") {
+            Some(LineEdit::Delete { target }) => {
+                assert_eq!(target, MessageTarget::Id(123456789012345678))
+            },
+            edit => panic!("unexpected edit {:?}", edit),
+        }
+    }
+
+    #[test]
+    fn not_a_line_edit() {
+        assert!(parse_line_edit("s/no delimiter").is_none());
+        assert!(parse_line_edit("yes/no").is_none());
+    }
+
+    #[test]
+    fn unicode_reaction_emoji() {
+        assert_eq!(
+            parse_reaction_emoji("👍"),
+            Some(ReactionEmoji::Unicode("👍"))
+        );
+        assert_eq!(
+            parse_reaction_emoji("👍🏽 "),
+            Some(ReactionEmoji::Unicode("👍🏽"))
+        );
+        assert_eq!(
+            parse_reaction_emoji("🏳️‍🌈"),
+            Some(ReactionEmoji::Unicode("🏳️‍🌈"))
+        );
+    }
+
+    #[test]
+    fn shortcode_and_custom_reaction_emoji() {
+        assert_eq!(
+            parse_reaction_emoji(":thumbsup:"),
+            Some(ReactionEmoji::Shortcode("thumbsup"))
+        );
+        assert_eq!(
+            parse_reaction_emoji("<a:party:123456789012345678>"),
+            Some(ReactionEmoji::Custom {
+                animated: true,
+                name: "party",
+                id: 123456789012345678,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_reaction_emoji() {
+        assert_eq!(parse_reaction_emoji("a"), None);
+        assert_eq!(parse_reaction_emoji("👍👍"), None);
+        assert_eq!(parse_reaction_emoji("👍 nice"), None);
+        assert_eq!(parse_reaction_emoji(":thumbsup: nice"), None);
+        assert_eq!(parse_reaction_emoji("<:x:1> y"), None);
+    }
+
+    #[test]
+    fn reactions() {
+        let reaction = parse_reaction("+👍").unwrap();
+        assert!(reaction.add);
+        assert_eq!(reaction.target, MessageTarget::Nth(1));
+        let reaction = parse_reaction("3-:smile:").unwrap();
+        assert!(!reaction.add);
+        assert_eq!(reaction.target, MessageTarget::Nth(3));
+        assert_eq!(reaction.emoji, ReactionEmoji::Shortcode("smile"));
+        assert!(parse_reaction("-1").is_none());
+        assert!(parse_reaction("+1 for that").is_none());
+    }
+
+    #[test]
+    fn reply() {
+        let reply = parse_reply(">2 sounds good ").unwrap();
+        assert_eq!(reply.line, 2);
+        assert_eq!(reply.text, "sounds good");
+    }
+
+    #[test]
+    fn not_a_reply() {
+        assert!(parse_reply(">0 text").is_none());
+        assert!(parse_reply(">2  ").is_none());
+        assert!(parse_reply(">2").is_none());
+        assert!(parse_reply("> quote").is_none());
+        assert!(parse_reply(">>> 2 quote").is_none());
+    }
+
+    #[test]
+    fn markdown_is_escaped() {
+        assert_eq!(escape_markdown("my_file*.txt"), "my\\_file\\*.txt");
+        assert_eq!(escape_markdown("a|b~c`d>e\\"), "a\\|b\\~c\\`d\\>e\\\\");
+        assert_eq!(escape_markdown("plain.png"), "plain.png");
+    }
 }
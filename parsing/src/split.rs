@@ -0,0 +1,226 @@
+//! Splitting long messages into parts Discord will accept
+
+const FENCE: &str = "```";
+
+/// Split text into parts of at most `limit` characters
+///
+/// Parts end on line boundaries where possible, then on whitespace. A code block that spans
+/// parts is closed at the end of one part and reopened, with the same language, in the next.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut splitter = Splitter {
+        limit,
+        parts: Vec::new(),
+        part: String::new(),
+        part_len: 0,
+        fresh: true,
+        fence: None,
+        last_line_start: 0,
+        opener_start: None,
+    };
+
+    for line in text.split('\n') {
+        // Like `parse_blocks`, an odd number of fences opens or closes a code block
+        let toggles_fence = line.matches(FENCE).count() % 2 == 1;
+        // Leave room to close a code block that is still open after this line
+        let reserve = if splitter.fence.is_some() != toggles_fence {
+            FENCE.len() + 1
+        } else {
+            0
+        };
+        splitter.push_line(line, reserve);
+
+        if toggles_fence {
+            splitter.fence = match splitter.fence {
+                Some(_) => None,
+                None => {
+                    let opener = opening_fence(line);
+                    // Only a line with nothing but the opener can be moved to the next part
+                    if line.trim() == opener {
+                        splitter.opener_start = Some(splitter.last_line_start);
+                    }
+                    Some(opener)
+                },
+            };
+        }
+    }
+
+    if !splitter.fresh {
+        splitter.parts.push(splitter.part);
+    }
+    splitter.parts
+}
+
+struct Splitter {
+    limit: usize,
+    parts: Vec<String>,
+    part: String,
+    part_len: usize,
+    /// The current part has no content other than a reopened code block
+    fresh: bool,
+    /// The fence and language that open the code block the current line is in
+    fence: Option<String>,
+    /// Where the last line starts in the current part
+    last_line_start: usize,
+    /// Where the opening line of a code block starts, if nothing has been added after it
+    opener_start: Option<usize>,
+}
+
+impl Splitter {
+    fn push_line(&mut self, line: &str, reserve: usize) {
+        let mut rest = line;
+        loop {
+            let separator = if self.part.is_empty() { 0 } else { 1 };
+            let available = self
+                .limit
+                .saturating_sub(self.part_len + separator + reserve)
+                .max(1);
+            let rest_len = rest.chars().count();
+            if rest_len <= available {
+                self.push(rest, rest_len);
+                return;
+            }
+            // Move the whole line to the next part before breaking it up
+            if !self.fresh {
+                self.finish_part();
+                continue;
+            }
+
+            let (chunk, remainder) = split_at_whitespace(rest, available);
+            self.push(chunk, chunk.chars().count());
+            self.finish_part();
+            if remainder.is_empty() {
+                return;
+            }
+            rest = remainder;
+        }
+    }
+
+    fn push(&mut self, text: &str, len: usize) {
+        self.last_line_start = self.part.len();
+        self.opener_start = None;
+        if !self.part.is_empty() {
+            self.part.push('\n');
+            self.part_len += 1;
+        }
+        self.part.push_str(text);
+        self.part_len += len;
+        self.fresh = false;
+    }
+
+    fn finish_part(&mut self) {
+        if let Some(start) = self.opener_start.take() {
+            // Don't end a part with an empty code block, the next part opens it instead
+            self.part.truncate(start);
+        } else if self.fence.is_some() {
+            self.part.push('\n');
+            self.part.push_str(FENCE);
+        }
+        if !self.part.is_empty() {
+            self.parts.push(std::mem::take(&mut self.part));
+        }
+        self.part_len = 0;
+        self.fresh = true;
+        if let Some(fence) = &self.fence {
+            self.part.push_str(fence);
+            self.part_len = fence.chars().count();
+        }
+    }
+}
+
+/// The fence that opens a code block on a line, with its language if it has one
+///
+/// The block is opened by the last fence on the line, a single word directly after it is the
+/// language, anything else is code.
+fn opening_fence(line: &str) -> String {
+    let after = match line.rfind(FENCE) {
+        Some(i) => &line[i + FENCE.len()..],
+        None => "",
+    };
+    let is_language = !after.is_empty()
+        && after
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '+' | '-' | '_' | '#' | '.'));
+    if is_language {
+        format!("{}{}", FENCE, after)
+    } else {
+        FENCE.to_owned()
+    }
+}
+
+/// Split off at most `max` characters, at the last whitespace if there is any
+fn split_at_whitespace(text: &str, max: usize) -> (&str, &str) {
+    let end = text
+        .char_indices()
+        .nth(max)
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| text.len());
+    match text[..end].rfind(char::is_whitespace) {
+        Some(i) if i > 0 => (&text[..i], text[i..].trim_start()),
+        _ => (&text[..end], &text[end..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_message_is_one_part() {
+        assert_eq!(split_message("hello\nworld", 20), vec!["hello\nworld"]);
+    }
+
+    #[test]
+    fn splits_on_lines_then_whitespace() {
+        assert_eq!(
+            split_message("one two\nthree four", 10),
+            vec!["one two", "three four"]
+        );
+        assert_eq!(
+            split_message("one two three four", 10),
+            vec!["one two", "three four"]
+        );
+        assert_eq!(split_message("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn limit_counts_characters() {
+        let parts = split_message(&"é".repeat(10), 5);
+        assert_eq!(parts, vec!["é".repeat(5), "é".repeat(5)]);
+    }
+
+    #[test]
+    fn code_block_is_reopened() {
+        let parts = split_message("```rust\nlet a = 1;\nlet b = 2;\n```", 24);
+        assert_eq!(
+            parts,
+            vec!["```rust\nlet a = 1;\n```", "```rust\nlet b = 2;\n```"]
+        );
+        for part in &parts {
+            assert!(part.chars().count() <= 24);
+        }
+    }
+
+    #[test]
+    fn empty_code_block_is_not_left_behind() {
+        assert_eq!(
+            split_message("intro text\n```\ncode line", 14),
+            vec!["intro text", "```\ncode\n```", "```\nline"]
+        );
+    }
+
+    #[test]
+    fn inline_fences_do_not_open_blocks() {
+        assert_eq!(
+            split_message("use ```x``` here\nnext line", 16),
+            vec!["use ```x``` here", "next line"]
+        );
+    }
+
+    #[test]
+    fn fence_language() {
+        assert_eq!(opening_fence("```rust"), "```rust");
+        assert_eq!(opening_fence("```"), "```");
+        assert_eq!(opening_fence("```let a = 1;"), "```");
+        assert_eq!(opening_fence("a ```b``` ```c++"), "```c++");
+    }
+}
@@ -1,4 +1,8 @@
 use crate::{
+    discord::{
+        forums::{self, Forum},
+        threads::{self, Thread},
+    },
     on_main,
     sync::on_main_blocking,
     utils,
@@ -72,20 +76,25 @@ pub fn create_buffers(ready_data: &Ready) {
         }
         create_guild_buffer(guild.id, &guild.name);
 
-        // TODO: Colors?
-        let nick = if let Ok(current_member) = guild.id.member(ctx, current_user.id) {
-            format!("@{}", current_member.display_name())
-        } else {
-            format!("@{}", current_user.name)
+        let nick = utils::guild_nick(ctx, guild.id, &current_user);
+        let channels = match utils::fetch_guild_channels(guild.id) {
+            Ok(channels) => channels,
+            Err(e) => {
+                crate::plugin_print(&format!(
+                    "Unable to fetch channels of {}: {}",
+                    guild.name, e
+                ));
+                continue;
+            },
         };
-        let channels = guild.id.channels(ctx).expect("Unable to fetch channels");
-        let mut channels = channels.values().collect::<Vec<_>>();
-        channels.sort_by_key(|g| g.position);
         for channel in channels {
             let is_muted =
                 guild_muted || channel_muted.get(&channel.id).cloned().unwrap_or_default();
             create_buffer_from_channel(&ctx.cache, &guild.name, &channel, &nick, is_muted);
         }
+        // Found while fetching the channels
+        let forums = forums::guild_forums(guild.id).unwrap_or_default();
+        create_forum_buffers(guild.id, &guild.name, &nick, &forums);
     }
 }
 
@@ -103,7 +112,7 @@ pub fn create_autojoin_buffers(_ready: &Ready) {
 
     let watched_items: Vec<_> = on_main_blocking(|weecord| weecord.config.watched_channels());
 
-    let watched_channels = utils::flatten_guilds(&ctx, &watched_items);
+    let watched_channels = utils::flatten_guilds(&watched_items);
 
     let cache = ctx.cache.read();
     for (guild_id, channels) in watched_channels {
@@ -127,7 +136,7 @@ pub fn create_autojoin_buffers(_ready: &Ready) {
     }
 
     // flatten guilds into channels
-    let autojoin_channels = utils::flatten_guilds(&ctx, &autojoin_items);
+    let autojoin_channels = utils::flatten_guilds(&autojoin_items);
 
     create_buffers_from_flat_items(&ctx, &current_user, &autojoin_channels);
 }
@@ -147,19 +156,25 @@ pub fn create_buffers_from_flat_items(
                 };
                 let guild = guild.read();
 
-                // TODO: Colors?
-                let nick = if let Ok(current_member) = guild.id.member(ctx, current_user.id) {
-                    format!("@{}", current_member.display_name())
-                } else {
-                    format!("@{}", current_user.name)
-                };
+                let nick = utils::guild_nick(ctx, guild.id, current_user);
                 let nick = &nick;
+                let guild_name = guild.name.clone();
 
                 create_guild_buffer(guild.id, &guild.name);
 
                 parking_lot::RwLockReadGuard::unlock_fair(guild);
 
                 for channel in channels {
+                    // Forums aren't cached, they are known once the guild's channels are fetched
+                    if channel.to_channel_cached(&ctx.cache).is_none() {
+                        let forum = forums::guild_forums(*guild_id)
+                            .ok()
+                            .and_then(|_| forums::get(*channel));
+                        if let Some(forum) = forum {
+                            create_forum_buffers(*guild_id, &guild_name, nick, &[forum]);
+                        }
+                        continue;
+                    }
                     // TODO: Muting
                     let () = on_main_blocking(move |_| {
                         let ctx = match crate::discord::get_ctx() {
@@ -322,6 +337,166 @@ pub fn create_buffer_from_channel(
     });
 }
 
+/// Forums are created separately as the serenity fork can't deserialize them
+pub fn create_forum_buffers(guild_id: GuildId, guild_name: &str, nick: &str, forums: &[Forum]) {
+    let () = on_main_blocking(|weecord| {
+        for forum in forums {
+            let buffer = weecord
+                .buffer_manager
+                .get_or_create_buffer(&utils::buffer_id_for_channel(Some(guild_id), forum.id));
+
+            buffer.set_short_name(&forum.name);
+            buffer.set_localvar("forumid", &forum.id.0.to_string());
+            buffer.set_localvar("guildid", &guild_id.0.to_string());
+            buffer.set_localvar("channel", &forum.name);
+            buffer.set_localvar("guild_name", guild_name);
+            buffer.set_localvar("server", guild_name);
+            buffer.set_localvar("type", "forum");
+            buffer.set_localvar("nick", nick);
+
+            let title = match &forum.topic {
+                Some(topic) if !topic.is_empty() => format!("{} | {}", forum.name, topic),
+                _ => forum.name.clone(),
+            };
+            buffer.set_title(&title);
+        }
+    });
+}
+
+pub fn forum_for_buffer(buffer: &Buffer) -> Option<Forum> {
+    buffer
+        .get_localvar("forumid")
+        .and_then(|id| id.parse::<u64>().ok())
+        .and_then(|id| forums::get(ChannelId(id)))
+}
+
+/// The nth post (starting at 1) in the order they were last listed in a forum buffer
+pub fn listed_post(buffer: &Buffer, n: usize) -> Option<Thread> {
+    let listed = buffer.get_localvar("forum_posts")?;
+    let id = listed.split(',').nth(n.checked_sub(1)?)?.parse().ok()?;
+    threads::get(ChannelId(id))
+}
+
+/// List the posts of a forum, numbered for `/discord open-post`
+pub fn load_forum_posts(buffer: &MessageManager) {
+    let forum = match forum_for_buffer(buffer) {
+        Some(forum) => forum,
+        None => return,
+    };
+
+    buffer.set_history_loaded();
+    buffer.clear();
+    let buffer_name = buffer.get_name().to_string();
+
+    std::thread::spawn(move || {
+        let posts = forums::fetch_posts(&forum);
+        on_main(move |weecord| {
+            let ctx = match crate::discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            let buf = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buf) => buf,
+                None => return,
+            };
+
+            let posts = match posts {
+                Ok(posts) => posts,
+                Err(e) => {
+                    buf.print(&format!(
+                        "{}\tUnable to load posts: {}",
+                        weecord.get_prefix("network"),
+                        e
+                    ));
+                    return;
+                },
+            };
+            if posts.is_empty() {
+                buf.print(&format!(
+                    "{}\tThere are no posts in this forum",
+                    weecord.get_prefix("network")
+                ));
+                return;
+            }
+
+            let listed = posts
+                .iter()
+                .map(|post| post.id.0.to_string())
+                .collect::<Vec<_>>();
+            buf.set_localvar("forum_posts", &listed.join(","));
+
+            let dim = weecord.color("8");
+            let reset = weecord.color("reset");
+            for (i, post) in posts.iter().enumerate() {
+                let mut title = format!("{}{}{}", weecord.color("bold"), post.name, reset);
+                if post.archived {
+                    title.push_str(&format!(" {}(archived){}", dim, reset));
+                }
+                buf.print(&format!("{}{}\t{}", dim, i + 1, title));
+
+                let mut details = Vec::new();
+                if let Some(owner) = post.owner_id {
+                    let author = ctx
+                        .cache
+                        .read()
+                        .users
+                        .get(&owner)
+                        .map(|user| user.read().name.clone())
+                        .unwrap_or_else(|| owner.0.to_string());
+                    details.push(format!("by {}", author));
+                }
+                let tags = forum.tag_names(post);
+                if !tags.is_empty() {
+                    details.push(tags.join(", "));
+                }
+                details.push(format!("{} replies", post.message_count));
+                if let Some(last) = post.last_activity() {
+                    details.push(format!(
+                        "last active {}",
+                        last.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+                    ));
+                }
+                buf.print(&format!("\t{}{}{}", dim, details.join(" · "), reset));
+            }
+        });
+    });
+}
+
+/// Create a buffer for a thread, named under its parent channel's buffer
+pub fn create_buffer_from_thread(weecord: &Discord, thread: &Thread) -> String {
+    let name_id = utils::buffer_id_for_channel(Some(thread.guild_id), thread.id);
+    let parent_name = utils::buffer_id_for_channel(Some(thread.guild_id), thread.parent_id);
+    let parent = weecord.buffer_manager.get_buffer(&parent_name);
+    let parent_channel = parent
+        .as_ref()
+        .and_then(|parent| parent.get_localvar("channel"))
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| thread.parent_id.0.to_string());
+
+    let buffer = weecord.buffer_manager.get_or_create_buffer(&name_id);
+    buffer.set_short_name(&thread.name);
+
+    buffer.set_localvar("channelid", &thread.id.0.to_string());
+    buffer.set_localvar("guildid", &thread.guild_id.0.to_string());
+    buffer.set_localvar("parentid", &thread.parent_id.0.to_string());
+    buffer.set_localvar("channel", &thread.name);
+    buffer.set_localvar("type", "channel");
+    if let Some(parent) = parent {
+        for var in &["guild_name", "server", "nick"] {
+            if let Some(value) = parent.get_localvar(var) {
+                buffer.set_localvar(var, &value);
+            }
+        }
+    }
+
+    let mut title = format!("{} | thread in #{}", thread.name, parent_channel);
+    if thread.archived {
+        title += " (archived)";
+    }
+    buffer.set_title(&title);
+    name_id
+}
+
 // TODO: Reduce code duplication
 pub fn create_buffer_from_dm(
     cache: &CacheRwLock,
@@ -478,6 +653,7 @@ pub fn load_history(
 
     buffer.clear();
     buffer.set_history_loaded();
+    buffer.set_older_history_exhausted(false);
 
     let buffer_name = buffer.get_name().to_string();
 
@@ -503,6 +679,10 @@ pub fn load_history(
                     let unread_in_page = msgs.iter().any(|m| m.id == read_state.last_message_id);
 
                     if unread_in_page {
+                        // Only mark the boundary if something after it is unread
+                        if msgs.first().map(|m| m.id) != Some(read_state.last_message_id) {
+                            buf.set_read_marker(&ctx.cache, Some(read_state.last_message_id));
+                        }
                         let mut backlog = true;
                         for msg in msgs.into_iter().rev() {
                             unknown_users.extend(buf.add_message(&ctx.cache, &msg, false));
@@ -528,16 +708,7 @@ pub fn load_history(
                     }
                 }
                 if let Some(guild) = guild {
-                    let msg = json::object! {
-                        "op" =>  OpCode::GetGuildMembers.num(),
-                        "d" => json::object! {
-                            "guild_id" => guild.0.to_string(),
-                            "user_ids" => (unknown_users.iter().map(|id| id.to_string())).collect::<Vec<_>>(),
-                            "nonce" => channel.0.to_string(),
-                        }
-                    };
-                    ctx.shard
-                        .websocket_message(gateway::Message::Text(msg.to_string()));
+                    request_guild_members(ctx, guild, channel, &unknown_users);
                 }
                 let _ = completion_sender.send(());
             });
@@ -545,6 +716,86 @@ pub fn load_history(
     });
 }
 
+/// Fetch messages from before the oldest message in the buffer and insert them above it
+pub fn load_older_history(buffer: &MessageManager, fetch_count: i32) {
+    let channel = match buffer.channel_id() {
+        Some(channel) => channel,
+        None => return,
+    };
+    let oldest = match buffer.oldest_message_id() {
+        Some(oldest) => oldest,
+        None => {
+            load_history(buffer, crossbeam_channel::unbounded().0, fetch_count);
+            return;
+        },
+    };
+    if buffer.loading_older_history() || buffer.older_history_exhausted() {
+        return;
+    }
+    let ctx = match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    buffer.set_loading_older_history(true);
+    let guild = buffer.guild_id();
+    let buffer_name = buffer.get_name().to_string();
+    let fetch_count = fetch_count.max(1).min(100) as u64;
+
+    std::thread::spawn(move || {
+        let msgs = channel.messages(ctx, |retriever| retriever.before(oldest).limit(fetch_count));
+        on_main(move |weecord| {
+            let buf = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buf) => buf,
+                None => return,
+            };
+            buf.set_loading_older_history(false);
+
+            let msgs = match msgs {
+                Ok(msgs) => msgs,
+                Err(e) => {
+                    buf.print(&format!(
+                        "{}\tUnable to load older messages: {}",
+                        weecord.get_prefix("network"),
+                        e
+                    ));
+                    return;
+                },
+            };
+            if (msgs.len() as u64) < fetch_count {
+                buf.set_older_history_exhausted(true);
+            }
+            if msgs.is_empty() {
+                return;
+            }
+
+            // Messages are returned newest first
+            let unknown_users = buf.prepend_messages(&ctx.cache, msgs.into_iter().rev().collect());
+            if let Some(guild) = guild {
+                request_guild_members(ctx, guild, channel, &unknown_users);
+            }
+        });
+    });
+}
+
+/// Ask the gateway for members that aren't cached yet, the buffer is redrawn when they arrive
+fn request_guild_members<'a>(
+    ctx: &Context,
+    guild: GuildId,
+    channel: ChannelId,
+    users: impl IntoIterator<Item = &'a UserId>,
+) {
+    let msg = json::object! {
+        "op" =>  OpCode::GetGuildMembers.num(),
+        "d" => json::object! {
+            "guild_id" => guild.0.to_string(),
+            "user_ids" => (users.into_iter().map(|id| id.to_string())).collect::<Vec<_>>(),
+            "nonce" => channel.0.to_string(),
+        }
+    };
+    ctx.shard
+        .websocket_message(gateway::Message::Text(msg.to_string()));
+}
+
 pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
     let weechat = buffer.get_weechat();
     let use_presence = crate::upgrade_plugin(&weechat).config.use_presence.value();
@@ -748,12 +999,7 @@ pub fn update_nick() {
     let current_user = ctx.cache.read().user.clone();
 
     for guild in current_user.guilds(ctx).expect("Unable to fetch guilds") {
-        // TODO: Colors?
-        let nick = if let Ok(current_member) = guild.id.member(ctx, current_user.id) {
-            format!("@{}", current_member.display_name())
-        } else {
-            format!("@{}", current_user.name)
-        };
+        let nick = utils::guild_nick(ctx, guild.id, &current_user);
 
         let channels = guild.id.channels(ctx).expect("Unable to fetch channels");
         on_main(move |weechat| {
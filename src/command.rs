@@ -1,12 +1,20 @@
 use crate::{
-    buffers, discord, on_main_blocking, plugin_print, upgrade_plugin, utils,
+    buffers, discord, on_main, on_main_blocking, plugin_print, upgrade_plugin, utils,
     utils::{BufferExt, ChannelExt, GuildOrChannel},
     weechat_utils::MessageManager,
     Discord,
 };
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
-use serenity::model::{gateway::Activity, user::OnlineStatus};
+use serenity::{
+    client::Context,
+    model::{
+        channel::{Message, ReactionType},
+        gateway::Activity,
+        id::{ChannelId, GuildId},
+        user::{OnlineStatus, User},
+    },
+};
 use std::{borrow::Cow, collections::VecDeque, sync::Arc};
 use weechat::{Buffer, CommandHook, ConfigOption, ReturnCode, Weechat};
 
@@ -96,7 +104,7 @@ fn run_command(buffer: &Buffer, cmd: &str) {
         "watch" => watch(weecord, &args),
         "nowatch" => nowatch(weecord, &args),
         "watched" => watched(weecord),
-        "autojoin" => autojoin(weecord, &args, buffer),
+        "autojoin" => autojoin(weecord, &args),
         "noautojoin" => noautojoin(weecord, &args),
         "autojoined" => autojoined(weecord),
         "status" => status(&args),
@@ -112,6 +120,68 @@ fn run_command(buffer: &Buffer, cmd: &str) {
                 rehistory(weecord, &args, &*buffer);
             }
         },
+        "reveal" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                reveal(&args, &*buffer);
+            }
+        },
+        "stats" => stats(weecord),
+        "queue" => queue(weecord, &args),
+        "threads" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                threads(&*buffer);
+            }
+        },
+        "join-thread" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                join_thread(&args, &*buffer);
+            }
+        },
+        "open-post" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                open_post(weecord, &args, &*buffer);
+            }
+        },
+        "new-post" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                new_post(&args, &*buffer);
+            }
+        },
+        "leave-thread" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                leave_thread(&args, &*buffer);
+            }
+        },
+        "reactions" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                reactions(&args, &*buffer);
+            }
+        },
+        "edits" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                edits(weecord, &args, &*buffer);
+            }
+        },
+        "more" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                more(weecord, &args, &*buffer);
+            }
+        },
+        "reply" => {
+            let buffer_name = buffer.get_name().to_string();
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                reply(&args, &*buffer);
+            }
+        },
         _ => {
             plugin_print("Unknown command");
         },
@@ -181,6 +251,7 @@ pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode
         plugin_print("join requires an guild name and optional channel name");
         ReturnCode::Error
     } else {
+        let rest = args.rest;
         let mut args = args.args.iter();
         let guild_name = match args.next() {
             Some(g) => g,
@@ -198,23 +269,43 @@ pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode
                 crate::utils::search_channel(&ctx.cache, guild_name, channel_name)
             {
                 let guild = guild.read();
+                let current_user = ctx.cache.read().user.clone();
+                let nick = utils::guild_nick(ctx, guild.id, &current_user);
                 buffers::create_guild_buffer(guild.id, &guild.name);
-                // TODO: Add correct nick handling
                 buffers::create_buffer_from_channel(
                     &ctx.cache,
                     &guild.name,
                     &channel.read(),
-                    &ctx.cache.read().user.name,
+                    &nick,
                     false,
                 );
                 return ReturnCode::OkEat;
             }
+            if let Some((guild, forum)) =
+                crate::utils::search_forum(&ctx.cache, guild_name, channel_name)
+            {
+                let guild = guild.read();
+                let current_user = ctx.cache.read().user.clone();
+                let nick = utils::guild_nick(ctx, guild.id, &current_user);
+                buffers::create_guild_buffer(guild.id, &guild.name);
+                buffers::create_forum_buffers(guild.id, &guild.name, &nick, &[forum]);
+                return ReturnCode::OkEat;
+            }
+            if let Some(guild) = crate::utils::search_guild(&ctx.cache, guild_name) {
+                let cmd = format!("/discord join {}", rest);
+                let fetching = fetch_forums_then(guild.read().id, move |weecord| {
+                    join(weecord, &Args::from_cmd(&cmd), verbose);
+                });
+                if fetching {
+                    return ReturnCode::OkEat;
+                }
+            }
         } else if let Some(guild) = crate::utils::search_guild(&ctx.cache, guild_name) {
             let guild = guild.read();
             let guild_id = guild.id;
             drop(guild);
 
-            let channels = utils::flatten_guilds(&ctx, &[GuildOrChannel::Guild(guild_id)]);
+            let channels = utils::flatten_guilds(&[GuildOrChannel::Guild(guild_id)]);
 
             buffers::create_buffers_from_flat_items(&ctx, &ctx.cache.read().user, &channels);
             return ReturnCode::OkEat;
@@ -227,7 +318,30 @@ pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode
     }
 }
 
-fn resolve_channel_id(guild_name: &str, channel_name: Option<&str>) -> Option<String> {
+/// Forums aren't cached, the first time a guild's forums are searched its channels are fetched
+/// in the background and `retry` runs once they are known
+///
+/// Returns false if the forums of the guild are already known
+fn fetch_forums_then<F: 'static + FnOnce(&Discord) + Send>(guild_id: GuildId, retry: F) -> bool {
+    if discord::forums::is_fetched(guild_id) {
+        return false;
+    }
+    std::thread::spawn(
+        move || match discord::forums::fetch_guild_channels(guild_id) {
+            Ok(_) => on_main(retry),
+            Err(e) => on_main(move |_| plugin_print(&format!("Unable to fetch channels: {}", e))),
+        },
+    );
+    true
+}
+
+/// Resolve the id of a guild or channel for the watched and autojoined options, forums may only
+/// be known after `retry` runs
+fn resolve_channel_id<F: 'static + FnOnce(&Discord) + Send>(
+    guild_name: &str,
+    channel_name: Option<&str>,
+    retry: F,
+) -> Option<String> {
     let ctx = match discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return None,
@@ -241,8 +355,16 @@ fn resolve_channel_id(guild_name: &str, channel_name: Option<&str>) -> Option<St
                 Some(guild.read().id),
                 channel.read().id,
             ))
+        } else if let Some((guild, forum)) =
+            crate::utils::search_forum(&ctx.cache, guild_name, channel_name)
+        {
+            Some(crate::utils::unique_id(Some(guild.read().id), forum.id))
         } else {
-            plugin_print("Unable to find server and channel");
+            let fetching = crate::utils::search_guild(&ctx.cache, guild_name)
+                .map_or(false, |guild| fetch_forums_then(guild.read().id, retry));
+            if !fetching {
+                plugin_print("Unable to find server and channel");
+            }
             None
         }
     } else if let Some(guild) = crate::utils::search_guild(&ctx.cache, guild_name) {
@@ -274,6 +396,7 @@ fn watch(weechat: &Weechat, args: &Args) {
         plugin_print("watch requires a guild name and optional channel name");
         return;
     }
+    let rest = args.rest;
     let mut args = args.args.iter().filter(|i| !i.is_empty());
     let guild_name = match args.next() {
         Some(g) => g,
@@ -281,7 +404,9 @@ fn watch(weechat: &Weechat, args: &Args) {
     };
     let channel_name = args.next();
 
-    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned()) {
+    let cmd = format!("/discord watch {}", rest);
+    let retry = move |weecord: &Discord| watch(weecord, &Args::from_cmd(&cmd));
+    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned(), retry) {
         Some(cid) => cid,
         None => return,
     };
@@ -303,6 +428,7 @@ fn nowatch(weechat: &Weechat, args: &Args) {
         plugin_print("nowatch requires a guild name and optional channel name");
         return;
     }
+    let rest = args.rest;
     let mut args = args.args.iter().filter(|i| !i.is_empty());
     let guild_name = match args.next() {
         Some(g) => g,
@@ -310,7 +436,9 @@ fn nowatch(weechat: &Weechat, args: &Args) {
     };
     let channel_name = args.next();
 
-    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned()) {
+    let cmd = format!("/discord nowatch {}", rest);
+    let retry = move |weecord: &Discord| nowatch(weecord, &Args::from_cmd(&cmd));
+    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned(), retry) {
         Some(cid) => cid,
         None => return,
     };
@@ -380,7 +508,7 @@ fn watched(weechat: &Weechat) {
     }
 }
 
-fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
+fn autojoin(weechat: &Weechat, args: &Args) {
     if args.args.is_empty() {
         plugin_print("autojoin requires a guild name and optional channel name");
         return;
@@ -392,7 +520,9 @@ fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
     };
     let channel_name = opts.next();
 
-    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned()) {
+    let cmd = format!("/discord autojoin {}", args.rest);
+    let retry = move |weecord: &Discord| autojoin(weecord, &Args::from_cmd(&cmd));
+    let new_channel_id = match resolve_channel_id(guild_name, channel_name.cloned(), retry) {
         Some(cid) => cid,
         None => return,
     };
@@ -406,7 +536,11 @@ fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
             "Now autojoining {} in {}",
             guild_name, channel_name
         ));
-        run_command(buffer, &format!("/discord join {}", args.rest));
+        join(
+            weechat,
+            &Args::from_cmd(&format!("/discord join {}", args.rest)),
+            true,
+        );
     } else {
         plugin_print(&format!("Now autojoining all channels in {}", guild_name))
     }
@@ -424,7 +558,9 @@ fn noautojoin(weechat: &Weechat, args: &Args) {
     };
     let channel_name = opts.next();
 
-    let channel_id = match resolve_channel_id(guild_name, channel_name.cloned()) {
+    let cmd = format!("/discord noautojoin {}", args.rest);
+    let retry = move |weecord: &Discord| noautojoin(weecord, &Args::from_cmd(&cmd));
+    let channel_id = match resolve_channel_id(guild_name, channel_name.cloned(), retry) {
         Some(cid) => cid,
         None => return,
     };
@@ -667,6 +803,10 @@ fn discord_fmt(cmd: &str, msg: &str, buffer: &Buffer) {
 }
 
 fn rehistory(weecord: &Discord, args: &Args, buffer: &MessageManager) {
+    if buffer.get_localvar("forumid").is_some() {
+        buffers::load_forum_posts(buffer);
+        return;
+    }
     buffer.clear();
     let default_fetch_count = weecord.config.message_fetch_count.value();
     let count = args
@@ -677,6 +817,566 @@ fn rehistory(weecord: &Discord, args: &Args, buffer: &MessageManager) {
     buffers::load_history(buffer, crossbeam_channel::unbounded().0, count);
 }
 
+fn more(weecord: &Discord, args: &Args, buffer: &MessageManager) {
+    let count = match args.args.front().map(|c| c.parse::<i32>()) {
+        Some(Ok(count)) if count > 0 && count <= 100 => count,
+        None => weecord.config.message_fetch_count.value(),
+        _ => {
+            plugin_print("more requires a message count between 1 and 100");
+            return;
+        },
+    };
+    if buffer.older_history_exhausted() {
+        plugin_print("There are no older messages in this channel");
+        return;
+    }
+    buffers::load_older_history(buffer, count);
+}
+
+fn reveal(args: &Args, buffer: &MessageManager) {
+    let ctx = match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let n = match args.args.front().map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        None => 1,
+        _ => {
+            plugin_print("reveal requires a positive message number");
+            return;
+        },
+    };
+
+    if !buffer.reveal_spoilers(&ctx.cache, n) {
+        plugin_print(&format!("There is no message {} in this buffer", n));
+    }
+}
+
+fn edits(weecord: &Discord, args: &Args, buffer: &MessageManager) {
+    let n = match args.args.front().map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        None => 1,
+        _ => {
+            plugin_print("edits requires a positive message number");
+            return;
+        },
+    };
+    let revisions = match buffer.revisions(n) {
+        Some(revisions) => revisions,
+        None => {
+            plugin_print(&format!("There is no message {} in this buffer", n));
+            return;
+        },
+    };
+    if revisions.len() < 2 {
+        plugin_print(&format!(
+            "Message {} has not been edited since it was loaded",
+            n
+        ));
+        return;
+    }
+
+    let channel_name = discord::get_ctx()
+        .and_then(|ctx| buffer.channel_id()?.to_channel_cached(&ctx.cache))
+        .map(|channel| channel.name())
+        .unwrap_or_else(|| buffer.get_name().to_string());
+
+    let edits_buffer = weecord
+        .buffer_manager
+        .get_or_create_buffer(&format!("Edits.{}", buffer.get_name()));
+    // There is no channel to load history for
+    edits_buffer.set_history_loaded();
+    edits_buffer.clear();
+    edits_buffer.set_title(&format!(
+        "Edit history of message {} in #{}",
+        n, channel_name
+    ));
+    edits_buffer.set_short_name(&format!("#{} edits", channel_name));
+
+    let mut previous: Option<&str> = None;
+    for (i, revision) in revisions.iter().enumerate() {
+        let time = revision
+            .timestamp
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S");
+        edits_buffer.print(&format!(
+            "{}\tRevision {} at {}",
+            weecord.get_prefix("network"),
+            i + 1,
+            time
+        ));
+        let content = match previous {
+            Some(previous) => {
+                discord::formatting::format_diff(weecord, previous, &revision.content)
+            },
+            None => revision.content.clone(),
+        };
+        edits_buffer.print(&format!("\t{}", content));
+        previous = Some(&revision.content);
+    }
+    edits_buffer.switch_to();
+}
+
+/// Fetch every user who reacted to a message with an emoji, Discord returns at most 100 users
+/// per request so they are paged through
+fn reaction_users(
+    ctx: &Context,
+    msg: &Message,
+    reaction_type: &ReactionType,
+) -> serenity::Result<Vec<User>> {
+    const PAGE_SIZE: u8 = 100;
+
+    let mut users = Vec::new();
+    let mut after = None;
+    loop {
+        let page = ctx.http.get_reaction_users(
+            msg.channel_id.0,
+            msg.id.0,
+            reaction_type,
+            PAGE_SIZE,
+            after,
+        )?;
+        let full = page.len() == usize::from(PAGE_SIZE);
+        after = page.last().map(|user| user.id.0);
+        users.extend(page);
+        if !full {
+            return Ok(users);
+        }
+    }
+}
+
+fn reactions(args: &Args, buffer: &MessageManager) {
+    let n = match args.args.front().map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        None => 1,
+        _ => {
+            plugin_print("reactions requires a positive message number");
+            return;
+        },
+    };
+    let msg = match buffer.nth_message(n) {
+        Some(msg) => msg,
+        None => {
+            plugin_print(&format!("There is no message {} in this buffer", n));
+            return;
+        },
+    };
+    if msg.reactions.is_empty() {
+        plugin_print(&format!("Message {} has no reactions", n));
+        return;
+    }
+    let buffer_name = buffer.get_name().to_string();
+
+    std::thread::spawn(move || {
+        let ctx = match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        let users = msg
+            .reactions
+            .iter()
+            .map(|reaction| {
+                let users = reaction_users(ctx, &msg, &reaction.reaction_type);
+                (reaction.clone(), users)
+            })
+            .collect::<Vec<_>>();
+
+        on_main(move |weecord| {
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            buffer.print(&format!(
+                "{}\tReactions on message {}:",
+                weecord.get_prefix("network"),
+                n
+            ));
+            for (reaction, users) in users {
+                let emoji = match utils::reaction_emoji(&reaction.reaction_type) {
+                    Some(emoji) => emoji,
+                    None => continue,
+                };
+                let users = match users {
+                    Ok(users) => users,
+                    Err(e) => {
+                        buffer.print(&format!(
+                            "{}\tUnable to fetch users for {}: {}",
+                            weecord.get_prefix("network"),
+                            emoji,
+                            e
+                        ));
+                        continue;
+                    },
+                };
+                let mut names = users
+                    .iter()
+                    .map(|user| utils::format_nick_color(weecord, &user.name))
+                    .collect::<Vec<_>>();
+                if reaction.count > users.len() as u64 {
+                    names.push(format!("and {} more", reaction.count - users.len() as u64));
+                }
+                buffer.print(&format!(
+                    "\t  {} {}: {}",
+                    emoji,
+                    reaction.count,
+                    names.join(", ")
+                ));
+            }
+        });
+    });
+}
+
+/// The channel threads of a buffer belong to, the parent channel if the buffer is a thread
+fn thread_parent(buffer: &MessageManager) -> Option<(GuildId, ChannelId)> {
+    let guild = buffer.guild_id()?;
+    let parent = buffer
+        .get_localvar("parentid")
+        .and_then(|id| id.parse::<u64>().ok())
+        .map(ChannelId)
+        .or_else(|| buffer.channel_id())?;
+    Some((guild, parent))
+}
+
+fn threads(buffer: &MessageManager) {
+    let (guild, parent) = match thread_parent(buffer) {
+        Some(ids) => ids,
+        None => {
+            plugin_print("threads must be used in a guild channel");
+            return;
+        },
+    };
+    let buffer_name = buffer.get_name().to_string();
+
+    std::thread::spawn(move || {
+        let fetched = discord::threads::fetch_active(guild);
+        on_main(move |weecord| {
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            if let Err(e) = fetched {
+                buffer.print(&format!(
+                    "{}\tUnable to fetch threads: {}",
+                    weecord.get_prefix("network"),
+                    e
+                ));
+                return;
+            }
+
+            let threads = discord::threads::in_channel(parent)
+                .into_iter()
+                .filter(|thread| !thread.archived)
+                .collect::<Vec<_>>();
+            if threads.is_empty() {
+                buffer.print(&format!(
+                    "{}\tThere are no active threads in this channel",
+                    weecord.get_prefix("network")
+                ));
+                return;
+            }
+            buffer.print(&format!(
+                "{}\tActive threads:",
+                weecord.get_prefix("network")
+            ));
+            for thread in &threads {
+                buffer.print(&format!(
+                    "\t  {} ({} messages) {}{}",
+                    thread.name,
+                    thread.message_count,
+                    weecord.color("8"),
+                    thread.id.0
+                ));
+            }
+        });
+    });
+}
+
+fn join_thread(args: &Args, buffer: &MessageManager) {
+    let (guild, parent) = match thread_parent(buffer) {
+        Some(ids) => ids,
+        None => {
+            plugin_print("join-thread must be used in a guild channel");
+            return;
+        },
+    };
+    let name = args.rest.trim().to_owned();
+    if name.is_empty() {
+        plugin_print("join-thread requires a thread name or id");
+        return;
+    }
+
+    std::thread::spawn(move || {
+        use crate::discord::threads;
+
+        let thread = match threads::find(parent, &name) {
+            Some(thread) => Ok(thread),
+            None => threads::fetch_active(guild).and_then(|_| {
+                threads::find(parent, &name).ok_or_else(|| format!("No thread named {}", name))
+            }),
+        };
+        let thread = thread.and_then(|thread| threads::join(thread.id).map(|_| thread));
+
+        on_main(move |weecord| match thread {
+            Ok(thread) => {
+                let buffer_name = buffers::create_buffer_from_thread(weecord, &thread);
+                if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                    buffer.switch_to();
+                }
+            },
+            Err(e) => plugin_print(&format!("Unable to join thread: {}", e)),
+        });
+    });
+}
+
+fn leave_thread(args: &Args, buffer: &MessageManager) {
+    let (_, parent) = match thread_parent(buffer) {
+        Some(ids) => ids,
+        None => {
+            plugin_print("leave-thread must be used in a guild channel");
+            return;
+        },
+    };
+    let name = args.rest.trim();
+    let thread = if name.is_empty() {
+        buffer
+            .get_localvar("parentid")
+            .and_then(|_| buffer.channel_id())
+            .and_then(discord::threads::get)
+    } else {
+        discord::threads::find(parent, name)
+    };
+    let thread = match thread {
+        Some(thread) => thread,
+        None => {
+            plugin_print("leave-thread requires a thread name or id, or to be used in a thread");
+            return;
+        },
+    };
+
+    std::thread::spawn(move || {
+        let left = discord::threads::leave(thread.id);
+        on_main(move |weecord| match left {
+            Ok(()) => {
+                let buffer_name = utils::buffer_id_for_channel(Some(thread.guild_id), thread.id);
+                match weecord.buffer_manager.get_buffer(&buffer_name) {
+                    Some(buffer) => buffer.print(&format!(
+                        "{}\tYou left this thread",
+                        weecord.get_prefix("quit")
+                    )),
+                    None => plugin_print(&format!("Left thread {}", thread.name)),
+                }
+            },
+            Err(e) => plugin_print(&format!("Unable to leave thread: {}", e)),
+        });
+    });
+}
+
+fn open_post(weecord: &Discord, args: &Args, buffer: &MessageManager) {
+    let forum = match buffers::forum_for_buffer(buffer) {
+        Some(forum) => forum,
+        None => {
+            plugin_print("open-post must be used in a forum");
+            return;
+        },
+    };
+    let post = args.rest.trim();
+    if post.is_empty() {
+        plugin_print("open-post requires a post number, title or id");
+        return;
+    }
+
+    // Numbers within the listing refer to the order posts were listed in the forum buffer,
+    // anything else is an id or a title
+    let listed = match parsing::MessageTarget::parse(Some(post)) {
+        Some(parsing::MessageTarget::Nth(n)) if n > 0 => buffers::listed_post(buffer, n),
+        _ => None,
+    };
+    let found = listed.or_else(|| discord::threads::find(forum.id, post));
+    match found {
+        Some(post) => {
+            let buffer_name = buffers::create_buffer_from_thread(weecord, &post);
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                buffer.switch_to();
+            }
+        },
+        None => plugin_print(&format!("There is no post {} in this forum", post)),
+    }
+}
+
+fn new_post(args: &Args, buffer: &MessageManager) {
+    let forum = match buffers::forum_for_buffer(buffer) {
+        Some(forum) => forum,
+        None => {
+            plugin_print("new-post must be used in a forum");
+            return;
+        },
+    };
+    let parts = args.rest.splitn(3, '|').map(str::trim).collect::<Vec<_>>();
+    let (title, tags, body) = match parts.as_slice() {
+        [title, tags, body] if !title.is_empty() && !body.is_empty() => (*title, *tags, *body),
+        _ => {
+            plugin_print(
+                "new-post requires a title, tags and a message: <title> | <tags> | <message>",
+            );
+            return;
+        },
+    };
+
+    let mut tag_ids = Vec::new();
+    for name in tags
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match forum.find_tag(name) {
+            Some(tag) => tag_ids.push(tag.id),
+            None => {
+                let available = forum
+                    .tags
+                    .iter()
+                    .map(|tag| tag.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                plugin_print(&format!(
+                    "Unknown tag {}, available tags are: {}",
+                    name, available
+                ));
+                return;
+            },
+        }
+    }
+
+    let title = title.to_owned();
+    let body = body.to_owned();
+    let forum_buffer = buffer.get_name().to_string();
+    std::thread::spawn(move || {
+        let post = discord::forums::create_post(&forum, &title, &tag_ids, &body);
+        on_main(move |weecord| match post {
+            Ok(post) => {
+                if let Some(forum_buffer) = weecord.buffer_manager.get_buffer(&forum_buffer) {
+                    buffers::load_forum_posts(&forum_buffer);
+                }
+                let buffer_name = buffers::create_buffer_from_thread(weecord, &post);
+                if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                    buffer.switch_to();
+                }
+            },
+            Err(e) => plugin_print(&format!("Unable to create post: {}", e)),
+        });
+    });
+}
+
+fn queue(weecord: &Discord, args: &Args) {
+    let pending = discord::send_queue::pending();
+    match args.args.front().copied() {
+        None => {
+            if pending.is_empty() {
+                plugin_print("There are no messages waiting to be sent");
+                return;
+            }
+            weecord.print("");
+            weecord.print("Messages waiting to be sent:");
+            for (i, msg) in pending.iter().enumerate() {
+                let buffer = weecord.buffer_manager.get_buffer(&msg.buffer_name);
+                let channel = buffer
+                    .as_ref()
+                    .and_then(|buffer| buffer.get_localvar("channel"))
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| msg.channel.0.to_string());
+                let status = match (&msg.last_error, msg.sending) {
+                    (_, true) => "sending".to_owned(),
+                    (Some(e), false) => format!("{} attempts, last error: {}", msg.attempts, e),
+                    (None, false) => "queued".to_owned(),
+                };
+                let preview = msg.content.lines().next().unwrap_or_default();
+                weecord.print(&format!(
+                    "  {}. #{}: {} {}({}){}",
+                    i + 1,
+                    channel,
+                    preview,
+                    weecord.color("8"),
+                    status,
+                    weecord.color("reset")
+                ));
+            }
+        },
+        Some("cancel") => {
+            let n = match args.args.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => n,
+                _ => {
+                    plugin_print("queue cancel requires a message number from /discord queue");
+                    return;
+                },
+            };
+            let msg = match pending.get(n - 1) {
+                Some(msg) => msg,
+                None => {
+                    plugin_print(&format!("There is no queued message {}", n));
+                    return;
+                },
+            };
+            match discord::send_queue::cancel(&msg.nonce) {
+                Ok(msg) => {
+                    if let Some(buffer) = weecord.buffer_manager.get_buffer(&msg.buffer_name) {
+                        buffer.resolve_pending(&msg.nonce, "cancelled");
+                    }
+                    plugin_print(&format!("Cancelled message {}", n));
+                },
+                Err(e) => plugin_print(&format!("Unable to cancel message {}: {}", n, e)),
+            }
+        },
+        Some(_) => plugin_print("queue only accepts cancel <n>"),
+    }
+}
+
+fn stats(weecord: &Discord) {
+    let mut buffers = weecord.buffer_manager.stats();
+    buffers.retain(|(_, count, _)| *count > 0);
+    buffers.sort_by_key(|(_, _, size)| std::cmp::Reverse(*size));
+
+    let total_messages: usize = buffers.iter().map(|(_, count, _)| count).sum();
+    let total_size: usize = buffers.iter().map(|(_, _, size)| size).sum();
+
+    weecord.print("");
+    weecord.print(&format!(
+        "Stored messages: {} in {} buffers, about {} KiB",
+        total_messages,
+        buffers.len(),
+        total_size / 1024
+    ));
+    weecord.print(&format!(
+        "Limits: {} per buffer, {} total (0 is unlimited)",
+        weecord.config.max_buffer_messages.value(),
+        weecord.config.max_total_messages.value()
+    ));
+    for (name, count, size) in buffers.iter().take(10) {
+        weecord.print(&format!(
+            "    {}: {} messages, about {} KiB",
+            name,
+            count,
+            size / 1024
+        ));
+    }
+}
+
+fn reply(args: &Args, buffer: &MessageManager) {
+    let n = match args.args.front().map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        _ => {
+            plugin_print("reply requires a positive message number");
+            return;
+        },
+    };
+    let text = args.rest[args.args[0].len()..].trim();
+    if text.is_empty() {
+        plugin_print("reply requires a message");
+        return;
+    }
+
+    crate::hook::send_reply(buffer, n, text);
+}
+
 const CMD_DESCRIPTION: weechat::CommandDescription = weechat::CommandDescription {
     name: "discord",
     description: "\
@@ -704,7 +1404,19 @@ Originally by https://github.com/khyperia/weechat-discord",
     unflip
     shrug
     spoiler
-    rehistory",
+    rehistory
+    more [<n>]
+    reveal [<n>]
+    reply <n> <message>
+    edits [<n>]
+    reactions [<n>]
+    queue [cancel <n>]
+    threads
+    join-thread <thread>
+    leave-thread [<thread>]
+    open-post <post>
+    new-post <title> | <tags> | <message>
+    stats",
     args_description: "
     connect: sign in to discord and open chat buffers
     disconnect: sign out of Discord
@@ -721,7 +1433,19 @@ Originally by https://github.com/khyperia/weechat-discord",
     noautostart: disable autostart
     status: set your Discord online status
     token: set Discord login token
-    rehistory: reload the history in the current buffer
+    rehistory: reload the history in the current buffer, or the posts in a forum
+    more: load n (default message_load_count) messages older than the oldest one in the buffer
+    reveal: show the spoilers in the nth most recent message (default 1)
+    reply: reply to the nth most recent message, `>n message` in the input also works
+    edits: open a buffer with every revision of the nth most recent message (default 1)
+    reactions: show who reacted to the nth most recent message (default 1)
+    threads: list the active threads in the current channel
+    join-thread: join a thread of the current channel by name or id and open it in a buffer
+    leave-thread: leave a thread, the current one if none is given
+    open-post: open a post of the current forum by number, title or id
+    new-post: create a post in the current forum, tags are comma separated and may be empty
+    queue: list messages waiting to be sent, or cancel one before it is sent
+    stats: show how many messages are stored and their approximate memory usage
     upload: upload a file to the current channel
 
 Examples:
@@ -756,5 +1480,17 @@ unflip || \
 shrug || \
 spoiler || \
 rehistory || \
+more || \
+reveal || \
+reply || \
+stats || \
+edits || \
+reactions || \
+queue cancel || \
+threads || \
+join-thread || \
+leave-thread || \
+open-post || \
+new-post || \
 join %(weecord_guild_completion) %(weecord_channel_completion)",
 };
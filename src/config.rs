@@ -1,4 +1,4 @@
-use crate::{utils, utils::GuildOrChannel};
+use crate::{discord::formatting::CodeColors, utils, utils::GuildOrChannel};
 use weechat::{
     BooleanOption, ConfigOption, ConfigSectionInfo, IntegerOption, StringOption, Weechat,
 };
@@ -14,6 +14,21 @@ pub struct Config {
     pub message_fetch_count: IntegerOption,
     pub user_typing_list_max: IntegerOption,
     pub user_typing_list_expanded: BooleanOption,
+    pub show_spoilers: BooleanOption,
+    pub escape_input_markdown: BooleanOption,
+    pub reply_ping: BooleanOption,
+    pub max_buffer_messages: IntegerOption,
+    pub max_total_messages: IntegerOption,
+    pub load_history_on_scroll: BooleanOption,
+    pub keep_deleted_messages: BooleanOption,
+    pub group_messages_window: IntegerOption,
+    pub color_code_text: StringOption,
+    pub color_code_keyword: StringOption,
+    pub color_code_type: StringOption,
+    pub color_code_literal: StringOption,
+    pub color_code_string: StringOption,
+    pub color_code_number: StringOption,
+    pub color_code_comment: StringOption,
     pub config: weechat::Config<()>,
 }
 
@@ -133,6 +148,176 @@ pub fn init(weechat: &Weechat) -> Config {
         None::<()>,
     );
 
+    let show_spoilers = section.new_boolean_option(
+        "show_spoilers",
+        "Always show the contents of spoilers instead of hiding them",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let escape_input_markdown = section.new_boolean_option(
+        "escape_input_markdown",
+        "Escape markdown characters in sent messages, so only weechat formatting (ctrl-c b/i/_) \
+         is applied",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let reply_ping = section.new_boolean_option(
+        "reply_ping",
+        "Mention the author of the message being replied to",
+        true,
+        true,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let max_buffer_messages = section.new_integer_option(
+        "max_buffer_messages",
+        "How many messages are kept per buffer for redrawing, the oldest are forgotten first (0 \
+         for no limit)",
+        "",
+        0,
+        1_000_000,
+        "1000",
+        "1000",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let max_total_messages = section.new_integer_option(
+        "max_total_messages",
+        "How many messages are kept across all buffers, the oldest are forgotten first (0 for no \
+         limit)",
+        "",
+        0,
+        10_000_000,
+        "50000",
+        "50000",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let load_history_on_scroll = section.new_boolean_option(
+        "load_history_on_scroll",
+        "Load older messages when a buffer is scrolled to its first line",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let keep_deleted_messages = section.new_boolean_option(
+        "keep_deleted_messages",
+        "Keep deleted messages in the buffer, dimmed and marked as deleted",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let group_messages_window = section.new_integer_option(
+        "group_messages_window",
+        "Only let weechat.look.prefix_same_nick replace the prefix of consecutive messages from \
+         the same author sent within this many seconds (0 to always let it)",
+        "",
+        0,
+        86400,
+        "0",
+        "0",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_section_info: ConfigSectionInfo<()> = ConfigSectionInfo {
+        name: "color",
+        ..Default::default()
+    };
+
+    let color_section = config.new_section(color_section_info);
+
+    let color_code_text = color_section.new_string_option(
+        "code_text",
+        "Color for plain text in highlighted code blocks",
+        "default",
+        "default",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_keyword = color_section.new_string_option(
+        "code_keyword",
+        "Color for keywords in highlighted code blocks",
+        "magenta",
+        "magenta",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_type = color_section.new_string_option(
+        "code_type",
+        "Color for types in highlighted code blocks",
+        "cyan",
+        "cyan",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_literal = color_section.new_string_option(
+        "code_literal",
+        "Color for literals (true, false, null...) in highlighted code blocks",
+        "lightred",
+        "lightred",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_string = color_section.new_string_option(
+        "code_string",
+        "Color for strings in highlighted code blocks",
+        "green",
+        "green",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_number = color_section.new_string_option(
+        "code_number",
+        "Color for numbers in highlighted code blocks",
+        "yellow",
+        "yellow",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let color_code_comment = color_section.new_string_option(
+        "code_comment",
+        "Color for comments in highlighted code blocks",
+        "darkgray",
+        "darkgray",
+        false,
+        None,
+        None::<()>,
+    );
+
     config.read();
 
     Config {
@@ -146,6 +331,21 @@ pub fn init(weechat: &Weechat) -> Config {
         message_fetch_count,
         user_typing_list_max,
         user_typing_list_expanded,
+        show_spoilers,
+        escape_input_markdown,
+        reply_ping,
+        max_buffer_messages,
+        max_total_messages,
+        load_history_on_scroll,
+        keep_deleted_messages,
+        group_messages_window,
+        color_code_text,
+        color_code_keyword,
+        color_code_type,
+        color_code_literal,
+        color_code_string,
+        color_code_number,
+        color_code_comment,
         config,
     }
 }
@@ -168,4 +368,17 @@ impl Config {
             .filter_map(utils::parse_id)
             .collect()
     }
+
+    /// The colors used for the token classes in highlighted code blocks
+    pub fn code_colors(&self) -> CodeColors {
+        CodeColors {
+            text: self.color_code_text.value().into_owned(),
+            keyword: self.color_code_keyword.value().into_owned(),
+            type_name: self.color_code_type.value().into_owned(),
+            literal: self.color_code_literal.value().into_owned(),
+            string: self.color_code_string.value().into_owned(),
+            number: self.color_code_number.value().into_owned(),
+            comment: self.color_code_comment.value().into_owned(),
+        }
+    }
 }
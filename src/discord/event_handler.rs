@@ -1,7 +1,12 @@
 use crate::{
-    buffers, discord, on_main, on_main_blocking, utils, weechat_utils::MessageManager, Discord,
+    buffers, discord,
+    discord::threads::{self, Thread},
+    on_main, on_main_blocking, utils,
+    weechat_utils::MessageManager,
+    Discord,
 };
 use lazy_static::lazy_static;
+use serde_json::Value;
 use serenity::{
     cache::CacheRwLock,
     model::{gateway::Ready, prelude::*},
@@ -172,6 +177,9 @@ impl EventHandler for Handler {
     }
 
     fn message(&self, ctx: Context, msg: Message) {
+        if let Some(thread) = threads::record_message(msg.channel_id, msg.id) {
+            refresh_thread_starter(&thread);
+        }
         let string_channel = utils::buffer_id_for_channel(msg.guild_id, msg.channel_id);
         let () = on_main_blocking(move |weecord| {
             if let Some(buffer) = weecord.buffer_manager.get_buffer(&string_channel) {
@@ -222,14 +230,7 @@ impl EventHandler for Handler {
                                 let current_user = ctx.cache.read().user.clone();
                                 let guild = guild.read();
 
-                                // TODO: Colors?
-                                let nick = if let Ok(current_member) =
-                                    guild.id.member(&ctx, current_user.id)
-                                {
-                                    format!("@{}", current_member.display_name())
-                                } else {
-                                    format!("@{}", current_user.name)
-                                };
+                                let nick = utils::guild_nick(&ctx, guild.id, &current_user);
 
                                 buffers::create_guild_buffer(guild.id, &guild.name);
                                 // TODO: Muting
@@ -260,9 +261,7 @@ impl EventHandler for Handler {
         channel_id: ChannelId,
         deleted_messages_ids: Vec<MessageId>,
     ) {
-        for message_id in deleted_messages_ids {
-            delete_message(&ctx, channel_id, message_id)
-        }
+        delete_messages(&ctx, channel_id, deleted_messages_ids)
     }
 
     fn message_update(
@@ -279,7 +278,10 @@ impl EventHandler for Handler {
             },
             Some(Channel::Group(channel)) => (None, channel.read().channel_id, event.id),
             Some(Channel::Private(channel)) => (None, channel.read().id, event.id),
-            _ => return,
+            _ => match threads::get(event.channel_id) {
+                Some(thread) => (Some(thread.guild_id), thread.id, event.id),
+                None => return,
+            },
         };
 
         let buffer_name = utils::buffer_id_for_channel(guild_id, channel_id);
@@ -393,6 +395,50 @@ impl EventHandler for Handler {
         }
     }
 
+    fn unknown(&self, ctx: Context, name: String, raw: Value) {
+        match name.as_str() {
+            "THREAD_CREATE" | "THREAD_UPDATE" => {
+                if let Some(thread) = Thread::from_json(&raw) {
+                    threads::insert(thread.clone());
+                    refresh_thread_starter(&thread);
+                    // Also sent when the user is added to an existing thread
+                    if name == "THREAD_CREATE" && raw["newly_created"].as_bool() == Some(true) {
+                        print_thread_created(&ctx, &thread);
+                    }
+                }
+            },
+            "THREAD_DELETE" => {
+                let id = match threads::snowflake(&raw["id"]) {
+                    Some(id) => ChannelId(id),
+                    None => return,
+                };
+                let buffer_name = match threads::get(id) {
+                    Some(thread) => utils::buffer_id_for_channel(Some(thread.guild_id), id),
+                    None => return,
+                };
+                if let Some(thread) = threads::remove(id) {
+                    refresh_thread_starter(&thread);
+                }
+                on_main(move |weecord| {
+                    if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                        buffer.print(&format!(
+                            "{}\tThis thread has been deleted",
+                            weecord.get_prefix("network")
+                        ));
+                    }
+                });
+            },
+            "THREAD_LIST_SYNC" => {
+                if let Some(list) = raw["threads"].as_array() {
+                    for thread in list.iter().filter_map(Thread::from_json) {
+                        threads::insert(thread);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
     fn user_update(&self, _ctx: Context, _old: CurrentUser, _new: CurrentUser) {
         thread::spawn(|| {
             // TODO: Update nicklist (and/or just rework all nick stuff)
@@ -401,9 +447,59 @@ impl EventHandler for Handler {
     }
 }
 
-fn delete_message(ctx: &Context, channel_id: ChannelId, deleted_message_id: MessageId) {
+/// The guild of a cached guild channel or a known thread
+fn channel_guild(ctx: &Context, channel_id: ChannelId) -> Option<GuildId> {
     if let Some(channel) = ctx.cache.read().channels.get(&channel_id) {
-        let guild_id = channel.read().guild_id;
+        return Some(channel.read().guild_id);
+    }
+    threads::get(channel_id).map(|thread| thread.guild_id)
+}
+
+/// Show that a thread was started in the buffer of its parent channel, the system message
+/// Discord sends for it can't be deserialized by the serenity fork
+fn print_thread_created(ctx: &Context, thread: &Thread) {
+    let owner = thread.owner_id.and_then(|owner| {
+        let cache = ctx.cache.read();
+        cache
+            .member(thread.guild_id, owner)
+            .map(|member| member.display_name().to_string())
+            .or_else(|| cache.user(owner).map(|user| user.read().name.clone()))
+    });
+    let buffer_name = utils::buffer_id_for_channel(Some(thread.guild_id), thread.parent_id);
+    let name = thread.name.clone();
+    on_main(move |weecord| {
+        if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+            let owner = match owner {
+                Some(owner) => utils::format_nick_color(weecord, &owner),
+                None => "Someone".to_owned(),
+            };
+            buffer.print(&format!(
+                "{}\t{} started a thread: {}",
+                weecord.get_prefix("network"),
+                owner,
+                name
+            ));
+        }
+    });
+}
+
+/// Redraw the message a thread was started from so its thread marker is up to date
+fn refresh_thread_starter(thread: &Thread) {
+    let buffer_name = utils::buffer_id_for_channel(Some(thread.guild_id), thread.parent_id);
+    let starter = thread.starter_message_id();
+    on_main(move |weecord| {
+        if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+            let ctx = match discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            buffer.refresh_message(&ctx.cache, starter);
+        }
+    });
+}
+
+fn delete_message(ctx: &Context, channel_id: ChannelId, deleted_message_id: MessageId) {
+    if let Some(guild_id) = channel_guild(ctx, channel_id) {
         let buffer_name = utils::buffer_id_for_channel(Some(guild_id), channel_id);
 
         on_main(move |weecord| {
@@ -419,13 +515,27 @@ fn delete_message(ctx: &Context, channel_id: ChannelId, deleted_message_id: Mess
     }
 }
 
+fn delete_messages(ctx: &Context, channel_id: ChannelId, deleted_message_ids: Vec<MessageId>) {
+    if let Some(guild_id) = channel_guild(ctx, channel_id) {
+        let buffer_name = utils::buffer_id_for_channel(Some(guild_id), channel_id);
+
+        on_main(move |weecord| {
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                let ctx = match discord::get_ctx() {
+                    Some(ctx) => ctx,
+                    _ => return,
+                };
+
+                buffer.delete_messages(&ctx.cache, &deleted_message_ids);
+            }
+        });
+    }
+}
+
 fn reaction_update(ctx: Context, reaction: Reaction) {
-    let guild_id = match ctx.cache.read().channel(&reaction.channel_id) {
-        Some(Channel::Guild(channel)) => {
-            let channel = channel.read();
-            Some(channel.guild_id)
-        },
-        _ => return,
+    let guild_id = match channel_guild(&ctx, reaction.channel_id) {
+        Some(guild_id) => Some(guild_id),
+        None => return,
     };
     let buffer_name = utils::buffer_id_for_channel(guild_id, reaction.channel_id);
     thread::spawn(move || {
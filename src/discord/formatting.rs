@@ -1,65 +1,176 @@
-use parsing::{self, MarkdownNode};
+use chrono::{Local, TimeZone};
+use parsing::{
+    self,
+    diff::Change,
+    highlight::{Token, TokenKind},
+    Block, Inline, ListMarker, MarkdownNode, Timestamp, TimestampStyle,
+};
 use std::{rc::Rc, sync::RwLock};
 use weechat::Weechat;
 
-pub fn discord_to_weechat(weechat: &Weechat, msg: &str) -> String {
-    let ast = parsing::parse_markdown(msg);
+/// Information about the message being rendered that affects how markdown is displayed
+#[derive(Debug, Clone, Copy)]
+pub struct RenderContext<'a> {
+    /// Unix timestamp that relative timestamps (`<t:..:R>`) are computed against
+    pub reference_time: i64,
+    /// Render spoiler contents instead of hiding them
+    pub show_spoilers: bool,
+    pub code_colors: &'a CodeColors,
+}
 
-    let mut out = String::new();
-    for node in &ast.0 {
-        out.push_str(&discord_to_weechat_reducer(
-            &weechat,
-            &*node.read().unwrap(),
-        ))
+/// The weechat color names used for each token class in highlighted code blocks
+#[derive(Debug, Clone, Default)]
+pub struct CodeColors {
+    pub text: String,
+    pub keyword: String,
+    pub type_name: String,
+    pub literal: String,
+    pub string: String,
+    pub number: String,
+    pub comment: String,
+}
+
+impl CodeColors {
+    fn get(&self, kind: TokenKind) -> &str {
+        match kind {
+            TokenKind::Text => &self.text,
+            TokenKind::Keyword => &self.keyword,
+            TokenKind::Type => &self.type_name,
+            TokenKind::Literal => &self.literal,
+            TokenKind::String => &self.string,
+            TokenKind::Number => &self.number,
+            TokenKind::Comment => &self.comment,
+        }
     }
-    out
 }
 
-fn collect_styles(weechat: &Weechat, styles: &[Rc<RwLock<MarkdownNode>>]) -> String {
-    styles
-        .iter()
-        .map(|s| discord_to_weechat_reducer(&weechat, &*s.read().unwrap()))
+pub fn discord_to_weechat(weechat: &Weechat, msg: &str, ctx: RenderContext) -> String {
+    parsing::parse_blocks(msg)
+        .into_iter()
+        .map(|block| match block {
+            Block::Markdown(text) => format_markdown(weechat, text, ctx),
+            Block::Heading { level, text } => {
+                let content = format_markdown(weechat, text, ctx);
+                if level == 1 {
+                    format!(
+                        "{}{}{}{}",
+                        weechat.color("bold"),
+                        weechat.color("underline"),
+                        content,
+                        weechat.color("reset")
+                    )
+                } else {
+                    format!(
+                        "{}{}{}",
+                        weechat.color("bold"),
+                        content,
+                        weechat.color("reset")
+                    )
+                }
+            },
+            Block::Subtext(text) => format!(
+                "{}{}{}",
+                weechat.color("8"),
+                format_markdown(weechat, text, ctx),
+                weechat.color("reset")
+            ),
+            Block::ListItem {
+                depth,
+                marker,
+                text,
+            } => {
+                let marker = match marker {
+                    ListMarker::Bullet if depth == 0 => "•".to_owned(),
+                    ListMarker::Bullet => "◦".to_owned(),
+                    ListMarker::Numbered(n) => format!("{}.", n),
+                };
+                format!(
+                    "{}{} {}",
+                    "  ".repeat(depth + 1),
+                    marker,
+                    format_markdown(weechat, text, ctx)
+                )
+            },
+        })
         .collect::<Vec<_>>()
-        .join("")
+        .join("\n")
+}
+
+fn format_markdown(weechat: &Weechat, text: &str, ctx: RenderContext) -> String {
+    let ast = parsing::parse_markdown(text);
+
+    collect_styles(weechat, &ast.0, ctx)
+}
+
+fn collect_styles(
+    weechat: &Weechat,
+    styles: &[Rc<RwLock<MarkdownNode>>],
+    ctx: RenderContext,
+) -> String {
+    let mut out = String::new();
+    // The text rule stops at punctuation, so runs of text nodes are joined before looking for
+    // inline syntax such as timestamps
+    let mut text = String::new();
+    for style in styles {
+        let node = style.read().unwrap();
+        if let MarkdownNode::Text(string) = &*node {
+            text.push_str(string);
+        } else {
+            out.push_str(&format_text(weechat, &text, ctx));
+            text.clear();
+            out.push_str(&discord_to_weechat_reducer(weechat, &node, ctx));
+        }
+    }
+    out.push_str(&format_text(weechat, &text, ctx));
+    out
 }
 
-// TODO: Spoilers, code syntax highlighting?
 // TODO: if the whole line is wrapped in *, render as CTCP ACTION rather than
 // as fully italicized message.
-fn discord_to_weechat_reducer(weechat: &Weechat, node: &MarkdownNode) -> String {
+fn discord_to_weechat_reducer(
+    weechat: &Weechat,
+    node: &MarkdownNode,
+    ctx: RenderContext,
+) -> String {
     use MarkdownNode::*;
     match node {
         Bold(styles) => format!(
             "{}{}{}",
             weechat.color("bold"),
-            collect_styles(weechat, styles),
+            collect_styles(weechat, styles, ctx),
             weechat.color("-bold")
         ),
         Italic(styles) => format!(
             "{}{}{}",
             weechat.color("italic"),
-            collect_styles(weechat, styles),
+            collect_styles(weechat, styles, ctx),
             weechat.color("-italic")
         ),
         Underline(styles) => format!(
             "{}{}{}",
             weechat.color("underline"),
-            collect_styles(weechat, styles),
+            collect_styles(weechat, styles, ctx),
             weechat.color("-underline")
         ),
         Strikethrough(styles) => format!(
             "{}~~{}~~{}",
             weechat.color("red"),
-            collect_styles(weechat, styles),
+            collect_styles(weechat, styles, ctx),
             weechat.color("-red")
         ),
-        Spoiler(styles) => format!(
+        Spoiler(styles) if ctx.show_spoilers => format!(
             "{}||{}||{}",
             weechat.color("italic"),
-            collect_styles(weechat, styles),
+            collect_styles(weechat, styles, ctx),
             weechat.color("-italic")
         ),
-        Text(string) => string.to_owned(),
+        Spoiler(styles) => format!(
+            "{}||{}||{}",
+            weechat.color("8"),
+            hide_spoiler(&plain_text(styles)),
+            weechat.color("resetcolor")
+        ),
+        Text(string) => format_text(weechat, string, ctx),
         InlineCode(string) => format!(
             "{}{}{}",
             weechat.color("*8"),
@@ -67,6 +178,10 @@ fn discord_to_weechat_reducer(weechat: &Weechat, node: &MarkdownNode) -> String
             weechat.color("reset")
         ),
         Code(language, text) => {
+            if let Some(tokens) = parsing::highlight::highlight(language, text) {
+                return format_highlighted_code(weechat, language, &tokens, ctx.code_colors);
+            }
+
             let (fmt, reset) = (weechat.color("*8"), weechat.color("reset"));
 
             format!(
@@ -78,15 +193,40 @@ fn discord_to_weechat_reducer(weechat: &Weechat, node: &MarkdownNode) -> String
                     .join("\n"),
             )
         },
-        BlockQuote(styles) => format_block_quote(collect_styles(weechat, styles).lines()),
+        BlockQuote(styles) => format_block_quote(collect_styles(weechat, styles, ctx).lines()),
         SingleBlockQuote(styles) => format_block_quote(
-            collect_styles(weechat, styles)
+            collect_styles(weechat, styles, ctx)
                 .lines()
                 .map(strip_leading_bracket),
         ),
     }
 }
 
+/// The text content of a tree of nodes, without any formatting
+fn plain_text(styles: &[Rc<RwLock<MarkdownNode>>]) -> String {
+    use MarkdownNode::*;
+    styles
+        .iter()
+        .map(|style| match &*style.read().unwrap() {
+            Bold(styles)
+            | Italic(styles)
+            | Underline(styles)
+            | Strikethrough(styles)
+            | Spoiler(styles)
+            | BlockQuote(styles)
+            | SingleBlockQuote(styles) => plain_text(styles),
+            Text(string) | InlineCode(string) | Code(_, string) => string.to_owned(),
+        })
+        .collect()
+}
+
+/// Replace every visible character with a block, keeping whitespace so the layout is preserved
+fn hide_spoiler(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_whitespace() { c } else { '█' })
+        .collect()
+}
+
 fn strip_leading_bracket(line: &str) -> &str {
     &line[line.find("> ").map(|x| x + 2).unwrap_or(0)..]
 }
@@ -94,3 +234,111 @@ fn strip_leading_bracket(line: &str) -> &str {
 fn format_block_quote<'a>(lines: impl Iterator<Item = &'a str>) -> String {
     lines.fold(String::new(), |acc, x| format!("{}▎{}\n", acc, x))
 }
+
+fn format_highlighted_code(
+    weechat: &Weechat,
+    language: &str,
+    tokens: &[Token],
+    colors: &CodeColors,
+) -> String {
+    let reset = weechat.color("reset");
+
+    let mut lines = vec![String::new()];
+    for token in tokens {
+        let color = weechat.color(colors.get(token.kind)).into_owned();
+        // Color codes do not carry over to the next line, so multi-line tokens (block comments,
+        // strings) have to be colored line by line
+        for (i, part) in token.text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(String::new());
+            }
+            if !part.is_empty() {
+                let line = lines.last_mut().expect("lines is never empty");
+                line.push_str(&format!("{}{}{}", color, part, reset));
+            }
+        }
+    }
+    if lines.len() > 1 && lines.last().map_or(false, String::is_empty) {
+        lines.pop();
+    }
+
+    format!("```{}\n{}\n```", language, lines.join("\n"))
+}
+
+fn format_text(weechat: &Weechat, text: &str, ctx: RenderContext) -> String {
+    parsing::parse_inline(text)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => text.to_owned(),
+            Inline::Timestamp(timestamp) => format_timestamp(&timestamp, ctx.reference_time),
+            Inline::MaskedLink { label, url } => format!(
+                "{}{}{} ({})",
+                weechat.color("underline"),
+                label,
+                weechat.color("-underline"),
+                url
+            ),
+        })
+        .collect()
+}
+
+fn format_timestamp(timestamp: &Timestamp, reference_time: i64) -> String {
+    let time = match Local.timestamp_opt(timestamp.unix, 0).single() {
+        Some(time) => time,
+        None => return format!("<t:{}>", timestamp.unix),
+    };
+
+    let format = match timestamp.style {
+        TimestampStyle::ShortTime => "%H:%M",
+        TimestampStyle::LongTime => "%H:%M:%S",
+        TimestampStyle::ShortDate => "%d/%m/%Y",
+        TimestampStyle::LongDate => "%-d %B %Y",
+        TimestampStyle::ShortDateTime => "%-d %B %Y %H:%M",
+        TimestampStyle::LongDateTime => "%A, %-d %B %Y %H:%M",
+        TimestampStyle::Relative => return format_relative_time(timestamp.unix - reference_time),
+    };
+    time.format(format).to_string()
+}
+
+fn format_relative_time(delta: i64) -> String {
+    const UNITS: &[(i64, &str)] = &[
+        (60 * 60 * 24 * 365, "year"),
+        (60 * 60 * 24 * 30, "month"),
+        (60 * 60 * 24, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+        (1, "second"),
+    ];
+
+    let seconds = delta.abs();
+    let (size, unit) = UNITS
+        .iter()
+        .find(|(size, _)| seconds >= *size)
+        .unwrap_or(&(1, "second"));
+    let count = seconds / size;
+    let amount = if count == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", count, unit)
+    };
+
+    if delta < 0 {
+        format!("{} ago", amount)
+    } else {
+        format!("in {}", amount)
+    }
+}
+
+/// Show the word level changes between two revisions, `[-removed-]` in red and `{+added+}` in
+/// green
+pub fn format_diff(weechat: &Weechat, old: &str, new: &str) -> String {
+    let reset = weechat.color("reset");
+    parsing::diff::diff_words(old, new)
+        .into_iter()
+        .map(|change| match change {
+            Change::Equal(text) => text.to_owned(),
+            Change::Delete(text) => format!("{}[-{}-]{}", weechat.color("red"), text, reset),
+            Change::Insert(text) => format!("{}{{+{}+}}{}", weechat.color("green"), text, reset),
+        })
+        .collect()
+}
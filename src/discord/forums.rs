@@ -0,0 +1,171 @@
+//! Forum channels, like threads these can't be deserialized by the serenity fork
+//!
+//! Posts in a forum are threads and are tracked with the other threads.
+
+use super::{
+    rest,
+    threads::{self, snowflake, Thread},
+};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use serenity::model::id::{ChannelId, GuildId};
+use std::collections::{HashMap, HashSet};
+
+const GUILD_FORUM: u64 = 15;
+
+/// How many archived posts are fetched along with the active ones
+const ARCHIVED_POST_COUNT: u64 = 25;
+
+#[derive(Debug, Clone)]
+pub struct ForumTag {
+    pub id: u64,
+    pub name: String,
+    pub emoji: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Forum {
+    pub id: ChannelId,
+    pub guild_id: GuildId,
+    pub name: String,
+    pub topic: Option<String>,
+    pub position: i64,
+    pub tags: Vec<ForumTag>,
+}
+
+impl Forum {
+    /// Parse a forum from a channel object, returns None for other channel types
+    pub fn from_json(value: &Value) -> Option<Forum> {
+        if value["type"].as_u64()? != GUILD_FORUM {
+            return None;
+        }
+        let tags = value["available_tags"]
+            .as_array()
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|tag| {
+                        Some(ForumTag {
+                            id: snowflake(&tag["id"])?,
+                            name: tag["name"].as_str()?.to_owned(),
+                            emoji: tag["emoji_name"].as_str().map(str::to_owned),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Forum {
+            id: ChannelId(snowflake(&value["id"])?),
+            guild_id: GuildId(snowflake(&value["guild_id"])?),
+            name: value["name"].as_str()?.to_owned(),
+            topic: value["topic"].as_str().map(str::to_owned),
+            position: value["position"].as_i64().unwrap_or(0),
+            tags,
+        })
+    }
+
+    /// Names of the tags applied to a post
+    pub fn tag_names(&self, post: &Thread) -> Vec<String> {
+        post.applied_tags
+            .iter()
+            .filter_map(|id| self.tags.iter().find(|tag| tag.id == *id))
+            .map(|tag| match &tag.emoji {
+                Some(emoji) => format!("{} {}", emoji, tag.name),
+                None => tag.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Find a tag by case insensitive name
+    pub fn find_tag(&self, name: &str) -> Option<&ForumTag> {
+        let name = name.to_lowercase();
+        self.tags.iter().find(|tag| tag.name.to_lowercase() == name)
+    }
+}
+
+lazy_static! {
+    static ref FORUMS: Mutex<HashMap<ChannelId, Forum>> = Mutex::new(HashMap::new());
+    /// Guilds whose channels have been fetched, so all of their forums are known
+    static ref FETCHED_GUILDS: Mutex<HashSet<GuildId>> = Mutex::new(HashSet::new());
+}
+
+pub fn get(id: ChannelId) -> Option<Forum> {
+    FORUMS.lock().get(&id).cloned()
+}
+
+/// Fetch the channels of a guild as raw json and remember the forums among them
+pub fn fetch_guild_channels(guild_id: GuildId) -> Result<Vec<Value>, String> {
+    let response = rest::get(&format!("/guilds/{}/channels", guild_id.0))?;
+    let channels = response.as_array().cloned().unwrap_or_default();
+
+    let mut known = FORUMS.lock();
+    known.retain(|_, forum| forum.guild_id != guild_id);
+    for forum in channels.iter().filter_map(Forum::from_json) {
+        known.insert(forum.id, forum);
+    }
+    FETCHED_GUILDS.lock().insert(guild_id);
+    Ok(channels)
+}
+
+/// The forums of a guild ordered by position, its channels are fetched if they haven't been
+pub fn guild_forums(guild_id: GuildId) -> Result<Vec<Forum>, String> {
+    if !is_fetched(guild_id) {
+        fetch_guild_channels(guild_id)?;
+    }
+    let mut forums = FORUMS
+        .lock()
+        .values()
+        .filter(|forum| forum.guild_id == guild_id)
+        .cloned()
+        .collect::<Vec<_>>();
+    forums.sort_by_key(|forum| forum.position);
+    Ok(forums)
+}
+
+/// Whether the channels of a guild have been fetched, so all of its forums are known
+pub fn is_fetched(guild_id: GuildId) -> bool {
+    FETCHED_GUILDS.lock().contains(&guild_id)
+}
+
+/// Find a known forum of a guild by name or id, this doesn't fetch the guild's channels
+pub fn find(guild_id: GuildId, name_or_id: &str) -> Option<Forum> {
+    let name = name_or_id.to_lowercase();
+    FORUMS
+        .lock()
+        .values()
+        .find(|forum| {
+            forum.guild_id == guild_id
+                && (parsing::weechat_arg_strip(&forum.name).to_lowercase() == name
+                    || forum.id.0.to_string() == name_or_id)
+        })
+        .cloned()
+}
+
+/// Fetch the active and most recent archived posts of a forum, most recently active first
+pub fn fetch_posts(forum: &Forum) -> Result<Vec<Thread>, String> {
+    threads::fetch_active(forum.guild_id)?;
+    let archived = rest::get(&format!(
+        "/channels/{}/threads/archived/public?limit={}",
+        forum.id.0, ARCHIVED_POST_COUNT
+    ))?;
+    for post in threads::from_json_list(&archived["threads"]) {
+        threads::insert(post);
+    }
+    Ok(threads::in_channel(forum.id))
+}
+
+/// Create a post, the body is sent as its first message
+pub fn create_post(forum: &Forum, title: &str, tags: &[u64], body: &str) -> Result<Thread, String> {
+    let response = rest::post(
+        &format!("/channels/{}/threads", forum.id.0),
+        &json!({
+            "name": title,
+            "applied_tags": tags.iter().map(u64::to_string).collect::<Vec<_>>(),
+            "message": { "content": body },
+        }),
+    )?;
+    let post = Thread::from_json(&response).ok_or("Unexpected response creating post")?;
+    threads::insert(post.clone());
+    Ok(post)
+}
@@ -7,6 +7,11 @@ use std::{sync::Arc, thread};
 mod client;
 mod event_handler;
 pub mod formatting;
+pub mod forums;
+pub mod rest;
+pub mod send_queue;
+pub mod threads;
+pub mod typing;
 
 pub use event_handler::TYPING_EVENTS;
 
@@ -21,6 +26,7 @@ lazy_static! {
 }
 
 pub fn init(weecord: &Discord, token: &str, irc_mode: bool) {
+    rest::set_token(token);
     let (discord_client, events) = match DiscordClient::start(weecord, token) {
         Ok(d) => d,
         Err(e) => {
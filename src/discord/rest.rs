@@ -0,0 +1,109 @@
+//! Requests for API endpoints the serenity fork does not support
+//!
+//! Responses are returned as raw json, callers are expected to pick out the fields they need.
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use reqwest::{Client, Method, StatusCode};
+use serde_json::Value;
+use std::fmt;
+
+const API_BASE: &str = "https://discord.com/api/v9";
+const USER_AGENT: &str = concat!(
+    "DiscordBot (https://github.com/terminal-discord/weechat-discord, ",
+    env!("CARGO_PKG_VERSION"),
+    ")"
+);
+
+lazy_static! {
+    static ref TOKEN: Mutex<Option<String>> = Mutex::new(None);
+    static ref CLIENT: Client = Client::new();
+}
+
+/// A failed request, with the status code if a response was received
+#[derive(Debug)]
+pub struct RequestError {
+    pub status: Option<StatusCode>,
+    /// Seconds to wait before retrying, sent with rate limited responses
+    pub retry_after: Option<f64>,
+    pub message: String,
+}
+
+impl RequestError {
+    /// Whether the request may succeed if it is retried later
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Set the token used to authorize requests, called when connecting
+pub fn set_token(token: &str) {
+    *TOKEN.lock() = Some(token.to_owned());
+}
+
+/// Make a request to `path`, relative to the api root
+///
+/// Blocks until a response is received, so must not be called on the main thread
+pub fn request(method: Method, path: &str, body: Option<&Value>) -> Result<Value, RequestError> {
+    let error = |status, retry_after, message| RequestError {
+        status,
+        retry_after,
+        message,
+    };
+    let token = TOKEN
+        .lock()
+        .clone()
+        .ok_or_else(|| error(None, None, "Not connected".to_owned()))?;
+
+    let mut request = CLIENT
+        .request(method, &format!("{}{}", API_BASE, path))
+        .header("Authorization", token)
+        .header("User-Agent", USER_AGENT);
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+
+    let mut response = request
+        .send()
+        .map_err(|e| error(None, None, e.to_string()))?;
+    let status = response.status();
+    if status == StatusCode::NO_CONTENT {
+        return Ok(Value::Null);
+    }
+    if !status.is_success() {
+        let body = response.json::<Value>().unwrap_or(Value::Null);
+        let message = body["message"]
+            .as_str()
+            .map(str::to_owned)
+            .unwrap_or_else(|| status.to_string());
+        return Err(error(Some(status), body["retry_after"].as_f64(), message));
+    }
+    response
+        .json::<Value>()
+        .map_err(|e| error(Some(status), None, e.to_string()))
+}
+
+pub fn get(path: &str) -> Result<Value, String> {
+    request(Method::GET, path, None).map_err(|e| e.message)
+}
+
+pub fn post(path: &str, body: &Value) -> Result<Value, String> {
+    request(Method::POST, path, Some(body)).map_err(|e| e.message)
+}
+
+pub fn put(path: &str) -> Result<Value, String> {
+    request(Method::PUT, path, None).map_err(|e| e.message)
+}
+
+pub fn delete(path: &str) -> Result<Value, String> {
+    request(Method::DELETE, path, None).map_err(|e| e.message)
+}
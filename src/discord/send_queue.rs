@@ -0,0 +1,241 @@
+//! Outgoing messages are queued per channel and sent in order by a background worker, so they
+//! are held through disconnects, rate limits and server errors instead of being lost
+//!
+//! Each message is sent with a nonce, the gateway echoes it back with the created message so
+//! the "sending" line in the buffer can be replaced by the real one.
+
+use super::rest::{self, RequestError};
+use crate::{on_main, utils::DISCORD_EPOCH};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use reqwest::Method;
+use serde_json::json;
+use serenity::model::{
+    channel::Message,
+    id::{ChannelId, MessageId},
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The longest time to wait between attempts, in seconds
+const MAX_BACKOFF: u64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct PendingMessage {
+    pub nonce: String,
+    pub channel: ChannelId,
+    pub buffer_name: String,
+    pub content: String,
+    pub reply_to: Option<ReplyTo>,
+    pub attempts: u32,
+    /// A request for this message is in flight, so it can no longer be cancelled
+    pub sending: bool,
+    pub last_error: Option<String>,
+}
+
+/// The message a queued message replies to
+#[derive(Debug, Clone, Copy)]
+pub struct ReplyTo {
+    pub message: MessageId,
+    /// Whether the author of the message is mentioned
+    pub ping: bool,
+}
+
+#[derive(Default)]
+struct SendQueue {
+    channels: HashMap<ChannelId, VecDeque<PendingMessage>>,
+    /// Channels with a worker sending their queue
+    workers: HashSet<ChannelId>,
+}
+
+lazy_static! {
+    static ref QUEUE: Mutex<SendQueue> = Mutex::new(SendQueue::default());
+}
+
+static NONCE_INCREMENT: AtomicU64 = AtomicU64::new(0);
+
+/// Nonces have the same shape as snowflakes, a timestamp followed by an increment
+fn new_nonce() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(DISCORD_EPOCH);
+    let increment = NONCE_INCREMENT.fetch_add(1, Ordering::Relaxed) & 0xfff;
+    (((millis - DISCORD_EPOCH) << 22) | increment).to_string()
+}
+
+/// Queue a message to be sent to a channel, returns its nonce
+pub fn enqueue(
+    buffer_name: &str,
+    channel: ChannelId,
+    content: String,
+    reply_to: Option<ReplyTo>,
+) -> String {
+    let nonce = new_nonce();
+    let mut queue = QUEUE.lock();
+    queue
+        .channels
+        .entry(channel)
+        .or_default()
+        .push_back(PendingMessage {
+            nonce: nonce.clone(),
+            channel,
+            buffer_name: buffer_name.to_owned(),
+            content,
+            reply_to,
+            attempts: 0,
+            sending: false,
+            last_error: None,
+        });
+    if queue.workers.insert(channel) {
+        thread::spawn(move || send_channel_queue(channel));
+    }
+    nonce
+}
+
+/// Every message waiting to be sent, in the order they were queued for each channel
+pub fn pending() -> Vec<PendingMessage> {
+    let queue = QUEUE.lock();
+    let mut pending = queue
+        .channels
+        .values()
+        .flat_map(|messages| messages.iter().cloned())
+        .collect::<Vec<_>>();
+    pending.sort_by(|a, b| {
+        a.nonce
+            .len()
+            .cmp(&b.nonce.len())
+            .then(a.nonce.cmp(&b.nonce))
+    });
+    pending
+}
+
+/// Remove a message from the queue before it is sent
+pub fn cancel(nonce: &str) -> Result<PendingMessage, String> {
+    let mut queue = QUEUE.lock();
+    for messages in queue.channels.values_mut() {
+        if let Some(index) = messages.iter().position(|msg| msg.nonce == nonce) {
+            if messages[index].sending {
+                return Err("The message is being sent".into());
+            }
+            return Ok(messages.remove(index).unwrap());
+        }
+    }
+    Err("There is no such message in the queue".into())
+}
+
+/// Send the queued messages of a channel in order until the queue is empty
+fn send_channel_queue(channel: ChannelId) {
+    loop {
+        let next = {
+            let mut queue = QUEUE.lock();
+            let next = queue
+                .channels
+                .get_mut(&channel)
+                .and_then(|messages| messages.front_mut());
+            match next {
+                Some(next) => {
+                    next.sending = true;
+                    next.attempts += 1;
+                    next.clone()
+                },
+                None => {
+                    queue.channels.remove(&channel);
+                    queue.workers.remove(&channel);
+                    return;
+                },
+            }
+        };
+
+        let mut body = json!({ "content": next.content, "nonce": next.nonce });
+        if let Some(reply_to) = next.reply_to {
+            body["message_reference"] = json!({ "message_id": reply_to.message.0.to_string() });
+            body["allowed_mentions"] = json!({
+                "parse": ["users", "roles", "everyone"],
+                "replied_user": reply_to.ping,
+            });
+        }
+        let result = rest::request(
+            Method::POST,
+            &format!("/channels/{}/messages", channel.0),
+            Some(&body),
+        );
+
+        match result {
+            Ok(response) => {
+                finish(&next);
+                let msg = serde_json::from_value::<Message>(response).ok();
+                on_main(move |weecord| {
+                    let buffer = match weecord.buffer_manager.get_buffer(&next.buffer_name) {
+                        Some(buffer) => buffer,
+                        None => return,
+                    };
+                    match (msg, crate::discord::get_ctx()) {
+                        // Resolve the pending line now in case the gateway doesn't echo it
+                        (Some(msg), Some(ctx)) => {
+                            buffer.add_message(&ctx.cache, &msg, false);
+                        },
+                        // Keep the pending line so the gateway echo can replace it by nonce
+                        _ => buffer.set_pending_status(&next.nonce, "sent"),
+                    }
+                });
+            },
+            Err(e) if e.is_transient() => {
+                let delay = backoff(&next, &e);
+                {
+                    let mut queue = QUEUE.lock();
+                    let front = queue
+                        .channels
+                        .get_mut(&channel)
+                        .and_then(|messages| messages.front_mut());
+                    if let Some(front) = front {
+                        front.sending = false;
+                        front.last_error = Some(e.message.clone());
+                    }
+                }
+                let status = format!("retrying in {}s: {}", delay.as_secs(), e);
+                on_main(move |weecord| {
+                    if let Some(buffer) = weecord.buffer_manager.get_buffer(&next.buffer_name) {
+                        buffer.set_pending_status(&next.nonce, &status);
+                    }
+                });
+                thread::sleep(delay);
+            },
+            Err(e) => {
+                finish(&next);
+                let status = format!("failed: {}", e);
+                on_main(move |weecord| {
+                    if let Some(buffer) = weecord.buffer_manager.get_buffer(&next.buffer_name) {
+                        buffer.resolve_pending(&next.nonce, &status);
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// Remove a message that is done, successfully or not, from the front of its queue
+fn finish(msg: &PendingMessage) {
+    let mut queue = QUEUE.lock();
+    if let Some(messages) = queue.channels.get_mut(&msg.channel) {
+        if messages.front().map(|front| &front.nonce) == Some(&msg.nonce) {
+            messages.pop_front();
+        }
+    }
+}
+
+/// How long to wait before the next attempt, rate limits say how long to wait, other errors
+/// back off exponentially
+fn backoff(msg: &PendingMessage, error: &RequestError) -> Duration {
+    match error.retry_after {
+        Some(seconds) => Duration::from_millis((seconds * 1000.0) as u64 + 100),
+        None => Duration::from_secs(
+            2u64.saturating_pow(msg.attempts.saturating_sub(1))
+                .min(MAX_BACKOFF),
+        ),
+    }
+}
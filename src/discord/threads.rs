@@ -0,0 +1,152 @@
+//! Threads are channels the serenity fork can't deserialize, so they are tracked here from raw
+//! gateway events and api responses instead of the cache
+
+use super::rest;
+use crate::utils::DISCORD_EPOCH;
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde_json::Value;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use std::collections::HashMap;
+
+const PUBLIC_THREAD: u64 = 11;
+const PRIVATE_THREAD: u64 = 12;
+const NEWS_THREAD: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct Thread {
+    pub id: ChannelId,
+    pub guild_id: GuildId,
+    pub parent_id: ChannelId,
+    pub name: String,
+    pub owner_id: Option<UserId>,
+    pub message_count: u64,
+    pub last_message_id: Option<MessageId>,
+    pub archived: bool,
+    /// Ids of the forum tags applied to a forum post
+    pub applied_tags: Vec<u64>,
+}
+
+impl Thread {
+    /// Parse a thread from a channel object, returns None for other channel types
+    pub fn from_json(value: &Value) -> Option<Thread> {
+        match value["type"].as_u64()? {
+            NEWS_THREAD | PUBLIC_THREAD | PRIVATE_THREAD => {},
+            _ => return None,
+        }
+        Some(Thread {
+            id: ChannelId(snowflake(&value["id"])?),
+            guild_id: GuildId(snowflake(&value["guild_id"])?),
+            parent_id: ChannelId(snowflake(&value["parent_id"])?),
+            name: value["name"].as_str()?.to_owned(),
+            owner_id: snowflake(&value["owner_id"]).map(UserId),
+            message_count: value["message_count"].as_u64().unwrap_or(0),
+            last_message_id: snowflake(&value["last_message_id"]).map(MessageId),
+            archived: value["thread_metadata"]["archived"]
+                .as_bool()
+                .unwrap_or(false),
+            applied_tags: value["applied_tags"]
+                .as_array()
+                .map(|tags| tags.iter().filter_map(snowflake).collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// When the last message was sent, taken from the timestamp in its snowflake
+    pub fn last_activity(&self) -> Option<DateTime<Utc>> {
+        let millis = (self.last_message_id?.0 >> 22) + DISCORD_EPOCH;
+        Utc.timestamp_millis_opt(millis as i64).single()
+    }
+
+    /// The message the thread was started from, threads share their id with it
+    pub fn starter_message_id(&self) -> MessageId {
+        MessageId(self.id.0)
+    }
+}
+
+/// Parse a list of threads from an api response
+pub fn from_json_list(value: &Value) -> Vec<Thread> {
+    value
+        .as_array()
+        .map(|threads| threads.iter().filter_map(Thread::from_json).collect())
+        .unwrap_or_default()
+}
+
+/// Snowflakes are sent as strings
+pub fn snowflake(value: &Value) -> Option<u64> {
+    value.as_str()?.parse().ok()
+}
+
+lazy_static! {
+    static ref THREADS: Mutex<HashMap<ChannelId, Thread>> = Mutex::new(HashMap::new());
+}
+
+pub fn insert(thread: Thread) {
+    THREADS.lock().insert(thread.id, thread);
+}
+
+pub fn remove(id: ChannelId) -> Option<Thread> {
+    THREADS.lock().remove(&id)
+}
+
+pub fn get(id: ChannelId) -> Option<Thread> {
+    THREADS.lock().get(&id).cloned()
+}
+
+/// The thread started from a message, if there is one
+pub fn started_from(message: MessageId) -> Option<Thread> {
+    get(ChannelId(message.0))
+}
+
+/// Count a new message in a thread, returns the updated thread
+pub fn record_message(id: ChannelId, message: MessageId) -> Option<Thread> {
+    let mut threads = THREADS.lock();
+    let thread = threads.get_mut(&id)?;
+    thread.message_count += 1;
+    thread.last_message_id = Some(message);
+    Some(thread.clone())
+}
+
+/// Known threads in a channel, most recently active first
+pub fn in_channel(parent_id: ChannelId) -> Vec<Thread> {
+    let mut threads = THREADS
+        .lock()
+        .values()
+        .filter(|thread| thread.parent_id == parent_id)
+        .cloned()
+        .collect::<Vec<_>>();
+    threads.sort_by_key(|thread| std::cmp::Reverse(thread.last_message_id.unwrap_or(MessageId(0))));
+    threads
+}
+
+/// Find a thread in a channel by id or case insensitive name
+pub fn find(parent_id: ChannelId, name_or_id: &str) -> Option<Thread> {
+    if let Ok(id) = name_or_id.parse::<u64>() {
+        if let Some(thread) = get(ChannelId(id)) {
+            return Some(thread);
+        }
+    }
+    let name = name_or_id.to_lowercase();
+    in_channel(parent_id)
+        .into_iter()
+        .find(|thread| thread.name.to_lowercase() == name)
+}
+
+/// Fetch the active threads of a guild and add them to the known threads
+pub fn fetch_active(guild_id: GuildId) -> Result<Vec<Thread>, String> {
+    let response = rest::get(&format!("/guilds/{}/threads/active", guild_id.0))?;
+    let threads = from_json_list(&response["threads"]);
+    for thread in &threads {
+        insert(thread.clone());
+    }
+    Ok(threads)
+}
+
+pub fn join(id: ChannelId) -> Result<(), String> {
+    rest::put(&format!("/channels/{}/thread-members/@me", id.0)).map(|_| ())
+}
+
+pub fn leave(id: ChannelId) -> Result<(), String> {
+    rest::delete(&format!("/channels/{}/thread-members/@me", id.0)).map(|_| ())
+}
@@ -0,0 +1,126 @@
+//! Sends typing indicators from a background thread, throttled per channel
+//!
+//! Discord shows a typing indicator for about 10 seconds, so it is sent at most once every
+//! 8 seconds while the user keeps typing. Clearing the input or sending a message ends the
+//! indicator, typing again sends a new one right away.
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serenity::model::id::ChannelId;
+use std::{
+    collections::HashMap,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const TYPING_INTERVAL: Duration = Duration::from_secs(8);
+
+enum TypingEvent {
+    /// The input of a channel's buffer changed, with the unix timestamp of the last message the
+    /// user sent to the channel if it is known
+    Input(ChannelId, Option<i64>),
+    /// The input of a channel's buffer was cleared
+    Cleared(ChannelId),
+    /// A message was sent, which ends the typing indicator
+    Sent(ChannelId),
+}
+
+#[derive(Default)]
+struct ChannelTyping {
+    /// When a typing indicator was last sent, None if it has ended
+    last_typing: Option<Instant>,
+    /// When a message was last sent, used to wait out slowmode
+    last_message: Option<Instant>,
+}
+
+lazy_static! {
+    static ref SENDER: Mutex<Option<Sender<TypingEvent>>> = Mutex::new(None);
+}
+
+fn send(event: TypingEvent) {
+    let mut sender = SENDER.lock();
+    let sender = sender.get_or_insert_with(|| {
+        let (tx, rx) = unbounded();
+        thread::spawn(move || run(rx));
+        tx
+    });
+    let _ = sender.send(event);
+}
+
+/// The user is typing in a channel, `last_sent` is the unix timestamp of their last message in
+/// it, used to wait out slowmode before this session has sent anything
+pub fn typing(channel: ChannelId, last_sent: Option<i64>) {
+    send(TypingEvent::Input(channel, last_sent));
+}
+
+/// The user cleared the input of a channel's buffer
+pub fn input_cleared(channel: ChannelId) {
+    send(TypingEvent::Cleared(channel));
+}
+
+/// The user sent a message to a channel
+pub fn message_sent(channel: ChannelId) {
+    send(TypingEvent::Sent(channel));
+}
+
+fn run(events: Receiver<TypingEvent>) {
+    let mut channels: HashMap<ChannelId, ChannelTyping> = HashMap::new();
+
+    for event in events {
+        match event {
+            TypingEvent::Input(channel, last_sent) => {
+                let state = channels.entry(channel).or_default();
+                let now = Instant::now();
+                if state.last_message.is_none() {
+                    state.last_message = last_sent.and_then(instant_from_unix);
+                }
+                if state
+                    .last_typing
+                    .map_or(false, |last| now.duration_since(last) < TYPING_INTERVAL)
+                {
+                    continue;
+                }
+                // Typing is pointless while slowmode prevents sending another message
+                if let Some(last_message) = state.last_message {
+                    if now.duration_since(last_message) < slowmode(channel) {
+                        continue;
+                    }
+                }
+
+                let ctx = match super::get_ctx() {
+                    Some(ctx) => ctx,
+                    None => continue,
+                };
+                state.last_typing = Some(now);
+                let _ = channel.broadcast_typing(&ctx.http);
+            },
+            TypingEvent::Cleared(channel) => {
+                if let Some(state) = channels.get_mut(&channel) {
+                    state.last_typing = None;
+                }
+            },
+            TypingEvent::Sent(channel) => {
+                let state = channels.entry(channel).or_default();
+                state.last_typing = None;
+                state.last_message = Some(Instant::now());
+            },
+        }
+    }
+}
+
+/// The `Instant` a unix timestamp in the past corresponds to
+fn instant_from_unix(timestamp: i64) -> Option<Instant> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    let elapsed = Duration::from_secs(now.saturating_sub(timestamp).max(0) as u64);
+    Instant::now().checked_sub(elapsed)
+}
+
+/// The slowmode interval (`rate_limit_per_user`) of a channel, zero if there is none
+pub fn slowmode(channel: ChannelId) -> Duration {
+    let seconds = super::get_ctx()
+        .and_then(|ctx| ctx.cache.read().guild_channel(channel))
+        .and_then(|channel| channel.read().slow_mode_rate)
+        .unwrap_or(0);
+    Duration::from_secs(seconds)
+}
@@ -1,8 +1,10 @@
 use crate::{
     buffers::load_pin_buffer_history,
     command::Args,
-    discord, on_main, plugin_print, utils,
+    discord::{self, send_queue, typing},
+    on_main, plugin_print, utils,
     utils::{BufferExt, ChannelExt},
+    weechat_utils::{hdata, MessageManager},
 };
 use crossbeam_channel::unbounded;
 use lazy_static::lazy_static;
@@ -11,21 +13,16 @@ use serenity::{
     model::{channel::ReactionType, prelude::*},
     prelude::*,
 };
-use std::{
-    iter::FromIterator,
-    sync::Arc,
-    thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::{iter::FromIterator, sync::Arc, thread, time::Duration};
 use weechat::{Buffer, CompletionPosition, ConfigOption, ReturnCode, Weechat};
 
-lazy_static! {
-    static ref LAST_TYPING_TIMESTAMP: Arc<Mutex<u64>> = Arc::new(Mutex::new(0));
-}
+/// The most characters Discord accepts in a message
+const MESSAGE_LIMIT: usize = 2000;
 
 pub struct HookHandles {
     _buffer_switch_handle: weechat::SignalHook<()>,
     _buffer_typing_handle: weechat::SignalHook<()>,
+    _window_scrolled_handle: weechat::SignalHook<()>,
     _command_handles: Vec<weechat::CommandHook<()>>,
     _query_handle: weechat::CommandRunHook<()>,
     _nick_handle: weechat::CommandRunHook<()>,
@@ -52,6 +49,12 @@ pub fn init(weechat: &Weechat) -> HookHandles {
         None,
     );
 
+    let _window_scrolled_handle = weechat.hook_signal(
+        "window_scrolled",
+        |_, weechat, value| handle_window_scrolled(weechat, value),
+        None,
+    );
+
     let _query_handle = weechat.hook_command_run(
         "/query",
         |_, ref buffer, ref command| {
@@ -116,6 +119,7 @@ pub fn init(weechat: &Weechat) -> HookHandles {
     HookHandles {
         _buffer_switch_handle,
         _buffer_typing_handle,
+        _window_scrolled_handle,
         _command_handles,
         _query_handle,
         _nick_handle,
@@ -134,6 +138,13 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
     } else {
         text
     };
+    if buffer.get_localvar("forumid").is_some() {
+        buffer.print(&format!(
+            "{}\tMessages can't be sent to a forum, use /discord new-post to create a post",
+            buffer.get_weechat().get_prefix("network")
+        ));
+        return;
+    }
     let channel = buffer.channel_id();
     let guild = buffer.guild_id();
 
@@ -143,82 +154,198 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
             _ => return,
         };
 
+        let buffer_name = buffer.get_name().to_string();
+        let weecord = crate::upgrade_plugin(&buffer.get_weechat());
+        let messages = match weecord.buffer_manager.get_buffer(&buffer_name) {
+            Some(messages) => messages,
+            None => return,
+        };
+
         if let Some(edit) = parsing::parse_line_edit(text) {
-            let weechat = buffer.get_weechat();
+            let own_messages = Some(ctx.cache.read().user.id);
             match edit {
-                parsing::LineEdit::Delete { line } => {
-                    if let Err(e) = crate::utils::get_users_nth_message(&ctx, channel, line)
-                        .map(|msg| channel.delete_message(&ctx.http, msg.id))
-                    {
-                        buffer.print(&format!(
-                            "{}\tAn error occurred deleting a message: {}",
-                            weechat.get_prefix("network"),
-                            e
-                        ));
-                    }
-                },
+                parsing::LineEdit::Delete { target } => act_on_message(
+                    &messages,
+                    channel,
+                    target,
+                    own_messages,
+                    "An error occurred deleting a message",
+                    move |ctx, msg| {
+                        channel
+                            .delete_message(&ctx.http, msg.id)
+                            .map_err(|e| e.to_string())
+                    },
+                ),
                 parsing::LineEdit::Sub {
-                    line,
+                    target,
                     old,
                     new,
                     options,
                 } => {
-                    // TODO: Clean this up, (try block)?
-                    if let Err(e) =
-                        crate::utils::get_users_nth_message(&ctx, channel, line).map(|mut msg| {
-                            let orig = msg.content.clone();
-                            msg.edit(ctx, |e| {
-                                if options.map(|o| o.contains('g')).unwrap_or_default() {
-                                    e.content(orig.replace(old, new))
-                                } else {
-                                    e.content(orig.replacen(old, new, 1))
-                                }
-                            })
-                        })
-                    {
-                        buffer.print(&format!(
-                            "{}\tAn error occurred editing a message: {}",
-                            weechat.get_prefix("network"),
-                            e
-                        ));
-                    }
+                    let options = options.to_owned();
+                    act_on_message(
+                        &messages,
+                        channel,
+                        target,
+                        own_messages,
+                        "Unable to edit message",
+                        move |ctx, mut msg| {
+                            let content = utils::substitute(&msg.content, &old, &new, &options)?;
+                            msg.edit(ctx, |e| e.content(content))
+                                .map_err(|e| e.to_string())
+                        },
+                    )
                 },
             }
             return;
         }
         if let Some(reaction) = parsing::parse_reaction(text) {
-            if let Ok(msgs) =
-                channel.messages(ctx, |retriever| retriever.limit(reaction.line as u64))
-            {
-                for (i, msg) in msgs.iter().enumerate() {
-                    if i + 1 == reaction.line {
-                        if reaction.add {
-                            let _ =
-                                msg.react(ctx, ReactionType::Unicode(reaction.unicode.to_string()));
-                        } else {
-                            let _ = channel.delete_reaction(
-                                &ctx,
-                                msg.id,
-                                None,
-                                ReactionType::Unicode(reaction.unicode.to_string()),
-                            );
-                        }
-                    }
-                }
-            }
+            let reaction_type = match utils::reaction_type(&ctx.cache, guild, reaction.emoji) {
+                Ok(reaction_type) => reaction_type,
+                Err(e) => {
+                    let weechat = buffer.get_weechat();
+                    buffer.print(&format!(
+                        "{}\tUnable to react to message: {}",
+                        weechat.get_prefix("network"),
+                        e
+                    ));
+                    return;
+                },
+            };
+            let add = reaction.add;
+            act_on_message(
+                &messages,
+                channel,
+                reaction.target,
+                None,
+                "Unable to react to message",
+                move |ctx, msg| {
+                    let result = if add {
+                        msg.react(ctx, reaction_type)
+                    } else {
+                        channel.delete_reaction(ctx, msg.id, None, reaction_type)
+                    };
+                    result.map_err(|e| e.to_string())
+                },
+            );
+            return;
+        }
+        if let Some(reply) = parsing::parse_reply(text) {
+            send_reply(&messages, reply.line, reply.text);
             return;
         }
-        let text = utils::create_mentions(&ctx.cache, guild, text);
-        let text = utils::expand_guild_emojis(&ctx.cache, guild, &text);
-        if let Err(e) = channel.say(ctx, text) {
-            let weechat = buffer.get_weechat();
+        let text = prepare_content(&buffer, ctx, guild, text);
+        // Sending a message means everything above it has been read
+        messages.set_read_marker(&ctx.cache, None);
+        typing::message_sent(channel);
+        // Parts are sent in order by the channel's send queue
+        for part in parsing::split::split_message(&text, MESSAGE_LIMIT) {
+            let nonce = send_queue::enqueue(&buffer_name, channel, part.clone(), None);
+            messages.add_pending(&nonce, &part);
+        }
+    }
+}
+
+/// Convert input formatting, mentions and emojis into what Discord expects
+fn prepare_content(buffer: &Buffer, ctx: &Context, guild: Option<GuildId>, text: &str) -> String {
+    let escape = crate::upgrade_plugin(&buffer.get_weechat())
+        .config
+        .escape_input_markdown
+        .value();
+    let text = parsing::weechat_to_discord(text, escape);
+    let text = utils::create_mentions(&ctx.cache, guild, &text);
+    utils::expand_guild_emojis(&ctx.cache, guild, &text)
+}
+
+/// Find the message an edit or reaction targets and act on it
+///
+/// The buffer is searched here, fetching older messages and the action itself run in the
+/// background, failures are printed in the buffer after `error`
+fn act_on_message<F>(
+    buffer: &MessageManager,
+    channel: ChannelId,
+    target: parsing::MessageTarget,
+    author: Option<UserId>,
+    error: &'static str,
+    action: F,
+) where
+    F: FnOnce(&Context, Message) -> Result<(), String> + Send + 'static,
+{
+    let lookup = match utils::lookup_message(buffer, target, author) {
+        Ok(lookup) => lookup,
+        Err(e) => {
             buffer.print(&format!(
-                "{}\tUnable to send message to {}: {:#?}",
-                weechat.get_prefix("network"),
-                channel.0,
+                "{}\t{}: {}",
+                buffer.get_weechat().get_prefix("network"),
+                error,
                 e
             ));
+            return;
+        },
+    };
+    let buffer_name = buffer.get_name().to_string();
+
+    thread::spawn(move || {
+        let ctx = match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let result = lookup
+            .resolve(ctx, channel, author)
+            .and_then(|msg| action(ctx, msg));
+        if let Err(e) = result {
+            on_main(move |weecord| {
+                if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                    buffer.print(&format!(
+                        "{}\t{}: {}",
+                        weecord.get_prefix("network"),
+                        error,
+                        e
+                    ));
+                }
+            });
         }
+    });
+}
+
+/// Send `text` as a reply to the nth most recent message in the buffer
+pub fn send_reply(buffer: &MessageManager, n: usize, text: &str) {
+    let weechat = buffer.get_weechat();
+    let ctx = match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let channel = match buffer.channel_id() {
+        Some(channel) => channel,
+        None => return,
+    };
+    let msg_id = match buffer.nth_message_id(n) {
+        Some(msg_id) => msg_id,
+        None => {
+            buffer.print(&format!(
+                "{}\tThere is no message {} to reply to",
+                weechat.get_prefix("network"),
+                n
+            ));
+            return;
+        },
+    };
+
+    let ping = crate::upgrade_plugin(&weechat).config.reply_ping.value();
+    let content = prepare_content(buffer, ctx, buffer.guild_id(), text);
+    let buffer_name = buffer.get_name().to_string();
+
+    // Sending a message means everything above it has been read
+    buffer.set_read_marker(&ctx.cache, None);
+    typing::message_sent(channel);
+    let mut reply_to = Some(send_queue::ReplyTo {
+        message: msg_id,
+        ping,
+    });
+    for part in parsing::split::split_message(&content, MESSAGE_LIMIT) {
+        // Only the first part is shown as a reply
+        let nonce = send_queue::enqueue(&buffer_name, channel, part.clone(), reply_to.take());
+        buffer.add_pending(&nonce, &part);
     }
 }
 
@@ -245,6 +372,11 @@ fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
                 return ReturnCode::Ok;
             }
 
+            if buffer.get_localvar("forumid").is_some() {
+                crate::buffers::load_forum_posts(&buffer);
+                return ReturnCode::Ok;
+            }
+
             let fetch_count = weecord.config.message_fetch_count.value();
 
             crate::buffers::load_history(&buffer, tx, fetch_count);
@@ -316,6 +448,29 @@ fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
     ReturnCode::Ok
 }
 
+fn handle_window_scrolled(weechat: &Weechat, data: weechat::SignalHookValue) -> ReturnCode {
+    let weecord = crate::upgrade_plugin(weechat);
+    if !weecord.config.load_history_on_scroll.value() {
+        return ReturnCode::Ok;
+    }
+
+    if let weechat::SignalHookValue::Pointer(window_ptr) = data {
+        if let Some(buffer_ptr) = hdata::scrolled_to_top(weechat, window_ptr) {
+            let buffer = unsafe { crate::utils::buffer_from_ptr(buffer_ptr) };
+            if let Some(buffer) = weecord
+                .buffer_manager
+                .get_buffer(buffer.get_name().as_ref())
+            {
+                if buffer.history_loaded() {
+                    let fetch_count = weecord.config.message_fetch_count.value();
+                    crate::buffers::load_older_history(&buffer, fetch_count);
+                }
+            }
+        }
+    }
+    ReturnCode::Ok
+}
+
 fn handle_buffer_typing(weechat: &Weechat, data: weechat::SignalHookValue) -> ReturnCode {
     if let weechat::SignalHookValue::Pointer(buffer_ptr) = data {
         let buffer = unsafe { crate::utils::buffer_from_ptr(buffer_ptr) };
@@ -325,33 +480,38 @@ fn handle_buffer_typing(weechat: &Weechat, data: weechat::SignalHookValue) -> Re
                 .send_typing_events
                 .value()
             {
-                if buffer.input().starts_with('/') {
+                let input = buffer.input();
+                if input.is_empty() {
+                    typing::input_cleared(channel_id);
                     return ReturnCode::Ok;
                 }
-                // TODO: Wait for user to type for 3 seconds
-                let now = SystemTime::now();
-                let timestamp_now = now
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Time went backwards")
-                    .as_secs() as u64;
-
-                if *LAST_TYPING_TIMESTAMP.lock() + 9 < timestamp_now {
-                    *LAST_TYPING_TIMESTAMP.lock() = timestamp_now;
-
-                    std::thread::spawn(move || {
-                        let ctx = match discord::get_ctx() {
-                            Some(s) => s,
-                            None => return,
-                        };
-                        let _ = channel_id.broadcast_typing(&ctx.http);
-                    });
+                // Commands and edits don't lead to a message being sent
+                if input.starts_with('/') || parsing::parse_line_edit(&input).is_some() {
+                    return ReturnCode::Ok;
                 }
+                typing::typing(channel_id, last_sent(weechat, &buffer));
             }
         }
     }
     ReturnCode::Ok
 }
 
+/// The unix timestamp of the last message the user sent in a buffer's channel, only looked up
+/// when the channel has slowmode
+fn last_sent(weechat: &Weechat, buffer: &Buffer) -> Option<i64> {
+    let channel = buffer.channel_id()?;
+    if typing::slowmode(channel).as_secs() == 0 {
+        return None;
+    }
+    let ctx = crate::discord::get_ctx()?;
+    let own_id = ctx.cache.read().user.id;
+    let messages = crate::upgrade_plugin(weechat)
+        .buffer_manager
+        .get_buffer(&buffer.get_name())?;
+    let msg = messages.find_nth_message(1, Some(own_id)).ok()?;
+    Some(msg.timestamp.timestamp())
+}
+
 fn handle_channel_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
     // Get the previous argument with should be the guild name
     // TODO: Generalize this?
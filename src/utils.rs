@@ -1,7 +1,11 @@
-use crate::weechat_utils::MessageManager;
+use crate::{
+    discord::forums::{self, Forum},
+    weechat_utils::MessageManager,
+};
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
-use regex::Regex;
+use parsing::MessageTarget;
+use regex::{Regex, RegexBuilder};
 use serenity::{
     cache::{Cache, CacheRwLock},
     model::{id::ChannelId, prelude::*},
@@ -10,6 +14,14 @@ use serenity::{
 use std::{borrow::Cow, sync::Arc};
 use weechat::{Buffer, ConfigOption, Weechat};
 
+/// Milliseconds from the unix epoch to the first second of 2015, snowflakes count from here
+pub const DISCORD_EPOCH: u64 = 1_420_070_400_000;
+
+/// How many messages are fetched at a time when looking for an older message
+const SEARCH_PAGE_SIZE: u64 = 100;
+/// The most pages fetched when looking for an older message
+const MAX_SEARCH_PAGES: usize = 10;
+
 #[derive(Debug, Clone, Copy)]
 pub enum GuildOrChannel {
     Guild(GuildId),
@@ -41,6 +53,61 @@ pub fn rgb_to_ansi(color: serenity::utils::Colour) -> u8 {
     16 + 36 * r + 6 * g + b
 }
 
+/// Format a size in bytes with a binary unit, such as `1.2 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Guess a content type from a file name, Discord does not always send one
+pub fn guess_mime_type(filename: &str) -> Option<&'static str> {
+    let extension = filename.rsplit('.').next()?.to_lowercase();
+    if extension.len() == filename.len() {
+        return None;
+    }
+    Some(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "js" => "text/javascript",
+        "rs" => "text/x-rust",
+        "py" => "text/x-python",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        _ => return None,
+    })
+}
+
 pub fn status_is_online(status: OnlineStatus) -> bool {
     use OnlineStatus::*;
     match status {
@@ -92,6 +159,17 @@ pub fn format_nick_color(weechat: &Weechat, nick: &String) -> String {
     return prefix.to_string() + nick + &suffix.to_string();
 }
 
+/// The `@` prefixed nick shown in the buffers of a guild, the member's display name if the
+/// current user is a member
+pub fn guild_nick(ctx: &Context, guild_id: GuildId, current_user: &CurrentUser) -> String {
+    // TODO: Colors?
+    if let Ok(current_member) = guild_id.member(ctx, current_user.id) {
+        format!("@{}", current_member.display_name())
+    } else {
+        format!("@{}", current_user.name)
+    }
+}
+
 pub fn colorize_string(weechat: &Weechat, color: &str, string: &str) -> String {
     if string.is_empty() {
         string.to_owned()
@@ -146,6 +224,12 @@ pub trait BufferExt {
 
     fn nicks_loaded(&self) -> bool;
     fn set_nicks_loaded(&self);
+
+    fn loading_older_history(&self) -> bool;
+    fn set_loading_older_history(&self, loading: bool);
+
+    fn older_history_exhausted(&self) -> bool;
+    fn set_older_history_exhausted(&self, exhausted: bool);
 }
 
 impl BufferExt for Buffer {
@@ -176,6 +260,22 @@ impl BufferExt for Buffer {
     fn set_nicks_loaded(&self) {
         self.set_localvar("loaded_nicks", "true");
     }
+
+    fn loading_older_history(&self) -> bool {
+        self.get_localvar("loading_older_history").as_deref() == Some("true")
+    }
+
+    fn set_loading_older_history(&self, loading: bool) {
+        self.set_localvar("loading_older_history", &loading.to_string());
+    }
+
+    fn older_history_exhausted(&self) -> bool {
+        self.get_localvar("older_history_exhausted").as_deref() == Some("true")
+    }
+
+    fn set_older_history_exhausted(&self, exhausted: bool) {
+        self.set_localvar("older_history_exhausted", &exhausted.to_string());
+    }
 }
 
 pub fn pins_for_channel(buffer: &MessageManager) -> Option<ChannelId> {
@@ -240,7 +340,11 @@ pub fn buffer_id_for_guild(id: GuildId) -> String {
 
 pub fn buffer_id_for_channel(guild_id: Option<GuildId>, channel_id: ChannelId) -> String {
     if let Some(guild_id) = guild_id {
-        format!("{}.{}", guild_id, channel_id.0)
+        // Threads are named under their parent channel
+        match crate::discord::threads::get(channel_id) {
+            Some(thread) => format!("{}.{}.{}", guild_id, thread.parent_id.0, channel_id.0),
+            None => format!("{}.{}", guild_id, channel_id.0),
+        }
     } else {
         format!("Private.{}", channel_id.0)
     }
@@ -287,6 +391,19 @@ pub fn search_channel(
     None
 }
 
+/// Find a forum by guild and forum name or id, serenity doesn't cache forums so only forums
+/// of guilds whose channels have been fetched are found
+pub fn search_forum(
+    cache: &CacheRwLock,
+    guild_name: &str,
+    forum_name: &str,
+) -> Option<(Arc<RwLock<Guild>>, Forum)> {
+    let guild = search_guild(cache, guild_name)?;
+    let guild_id = guild.read().id;
+    let forum = forums::find(guild_id, forum_name)?;
+    Some((guild, forum))
+}
+
 pub fn search_guild(cache: &CacheRwLock, guild_name: &str) -> Option<Arc<RwLock<Guild>>> {
     for guild in cache.read().guilds.values() {
         let guild_lock = guild.read();
@@ -299,23 +416,33 @@ pub fn search_guild(cache: &CacheRwLock, guild_name: &str) -> Option<Arc<RwLock<
     None
 }
 
+/// Fetch the channels of a guild ordered by position, forums among them are remembered in
+/// `forums` as serenity can't deserialize them
+pub fn fetch_guild_channels(guild_id: GuildId) -> Result<Vec<GuildChannel>, String> {
+    let mut channels = forums::fetch_guild_channels(guild_id)?
+        .into_iter()
+        .filter_map(|channel| serde_json::from_value::<GuildChannel>(channel).ok())
+        .collect::<Vec<_>>();
+    channels.sort_by_key(|channel| channel.position);
+    Ok(channels)
+}
+
 /// Take a slice of `GuildOrChannel`'s and flatten it into a map of channels
-pub fn flatten_guilds(
-    ctx: &Context,
-    items: &[GuildOrChannel],
-) -> IndexMap<Option<GuildId>, Vec<ChannelId>> {
+pub fn flatten_guilds(items: &[GuildOrChannel]) -> IndexMap<Option<GuildId>, Vec<ChannelId>> {
     let mut channels: IndexMap<Option<GuildId>, Vec<ChannelId>> = IndexMap::new();
     // flatten guilds into channels
     for item in items {
         match item {
             GuildOrChannel::Guild(guild_id) => {
-                let guild_channels = guild_id.channels(ctx).unwrap_or_default();
-                let mut guild_channels = guild_channels.values().collect::<Vec<_>>();
-                guild_channels.sort_by_key(|g| g.position);
-                channels
-                    .entry(Some(*guild_id))
-                    .or_default()
-                    .extend(guild_channels.iter().map(|ch| ch.id));
+                let guild_channels = fetch_guild_channels(*guild_id).unwrap_or_default();
+                // Known from fetching the channels
+                let guild_forums = forums::guild_forums(*guild_id).unwrap_or_default();
+                channels.entry(Some(*guild_id)).or_default().extend(
+                    guild_channels
+                        .iter()
+                        .map(|ch| ch.id)
+                        .chain(guild_forums.iter().map(|forum| forum.id)),
+                );
             },
             GuildOrChannel::Channel(guild, channel) => {
                 channels.entry(*guild).or_default().push(*channel);
@@ -326,30 +453,175 @@ pub fn flatten_guilds(
     channels
 }
 
-pub fn get_users_nth_message(
+/// A message an edit or reaction refers to, or where to keep looking for it when it isn't
+/// stored in the buffer
+pub enum MessageLookup {
+    Found(Message),
+    /// The message is fetched by its id
+    FetchId(MessageId),
+    /// The nth message before `before` is paged for
+    FetchNth {
+        n: usize,
+        before: Option<MessageId>,
+    },
+}
+
+/// Find the message an edit or reaction refers to in the messages stored in the buffer,
+/// indices only count messages by `author` if it is given
+pub fn lookup_message(
+    buffer: &MessageManager,
+    target: MessageTarget,
+    author: Option<UserId>,
+) -> Result<MessageLookup, String> {
+    match target {
+        MessageTarget::Id(id) => Ok(match buffer.message(MessageId(id)) {
+            Some(msg) => MessageLookup::Found(msg),
+            None => MessageLookup::FetchId(MessageId(id)),
+        }),
+        MessageTarget::Nth(0) => Err("Messages are numbered from 1".into()),
+        MessageTarget::Nth(n) => Ok(match buffer.find_nth_message(n, author) {
+            Ok(msg) => MessageLookup::Found(msg),
+            Err((found, before)) => MessageLookup::FetchNth {
+                n: n - found,
+                before,
+            },
+        }),
+    }
+}
+
+impl MessageLookup {
+    /// Fetch the message if it wasn't found in the buffer, this blocks on requests so it must
+    /// not be called from the main thread
+    pub fn resolve(
+        self,
+        ctx: &Context,
+        channel: ChannelId,
+        author: Option<UserId>,
+    ) -> Result<Message, String> {
+        match self {
+            MessageLookup::Found(msg) => Ok(msg),
+            MessageLookup::FetchId(id) => channel.message(&ctx.http, id).map_err(|e| e.to_string()),
+            MessageLookup::FetchNth { n, before } => {
+                fetch_nth_message(ctx, channel, n, author, before)
+            },
+        }
+    }
+}
+
+/// Page back through the history of a channel for the nth message before `before`
+fn fetch_nth_message(
     ctx: &Context,
     channel: ChannelId,
-    n: usize,
-) -> serenity::Result<Message> {
-    if n > 100 {
-        return Err(serenity::Error::ExceededLimit(
-            "Cannot fetch more than 100 items".into(),
-            n as u32,
-        ));
-    }
-    let user = ctx.cache.read().user.id;
-    // TODO: Page if needed
-    channel
-        .messages(&ctx.http, |retriever| retriever.limit(50))
-        .and_then(|msgs| {
-            msgs.iter()
-                .filter(|msg| msg.author.id == user)
-                .nth(n - 1)
-                .cloned()
-                .ok_or(serenity::Error::Model(
-                    serenity::model::ModelError::ItemMissing,
-                ))
-        })
+    mut n: usize,
+    author: Option<UserId>,
+    mut before: Option<MessageId>,
+) -> Result<Message, String> {
+    for _ in 0..MAX_SEARCH_PAGES {
+        let msgs = channel
+            .messages(&ctx.http, |retriever| {
+                retriever.limit(SEARCH_PAGE_SIZE);
+                match before {
+                    Some(before) => retriever.before(before),
+                    None => retriever,
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        for msg in &msgs {
+            if author.map_or(true, |author| msg.author.id == author) {
+                n -= 1;
+                if n == 0 {
+                    return Ok(msg.clone());
+                }
+            }
+        }
+        match msgs.last() {
+            Some(last) if msgs.len() as u64 == SEARCH_PAGE_SIZE => before = Some(last.id),
+            _ => return Err("There is no such message".into()),
+        }
+    }
+    Err(format!(
+        "The message is more than {} messages back",
+        MAX_SEARCH_PAGES as u64 * SEARCH_PAGE_SIZE
+    ))
+}
+
+/// Apply a sed style substitution (`s/old/new/options`) to `input`
+///
+/// `old` is a regex, `new` may reference capture groups with `\1`..`\9` and the whole match
+/// with `&`. The supported options are `g` (replace every match), `i` (case insensitive) and a
+/// number `N` (replace the Nth match, or every match from the Nth when combined with `g`)
+pub fn substitute(input: &str, old: &str, new: &str, options: &str) -> Result<String, String> {
+    let mut global = false;
+    let mut case_insensitive = false;
+    let mut nth = String::new();
+    for flag in options.chars() {
+        match flag {
+            'g' => global = true,
+            'i' | 'I' => case_insensitive = true,
+            c if c.is_ascii_digit() => nth.push(c),
+            c if c.is_whitespace() => {},
+            c => return Err(format!("Unknown substitution flag '{}'", c)),
+        }
+    }
+    let nth = if nth.is_empty() {
+        1
+    } else {
+        match nth.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Invalid occurrence number '{}'", nth)),
+        }
+    };
+
+    let regex = RegexBuilder::new(old)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))?;
+    let replacement = sed_replacement(new);
+
+    let mut out = String::new();
+    let mut last = 0;
+    let mut replaced = false;
+    for (i, caps) in regex.captures_iter(input).enumerate() {
+        let occurrence = i + 1;
+        if occurrence < nth {
+            continue;
+        }
+        if occurrence > nth && !global {
+            break;
+        }
+        let whole = caps.get(0).expect("capture 0 is always present");
+        out.push_str(&input[last..whole.start()]);
+        caps.expand(&replacement, &mut out);
+        last = whole.end();
+        replaced = true;
+    }
+
+    if !replaced {
+        return Err(format!("No match for '{}'", old));
+    }
+    out.push_str(&input[last..]);
+    Ok(out)
+}
+
+/// Translate a sed replacement string into the syntax used by `regex`
+fn sed_replacement(new: &str) -> String {
+    let mut out = String::new();
+    let mut chars = new.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{}}}", d)),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            c => out.push(c),
+        }
+    }
+    out
 }
 
 // TODO: Role mentions
@@ -461,6 +733,54 @@ pub fn expand_guild_emojis(cache: &CacheRwLock, guild_id: Option<GuildId>, input
     out
 }
 
+/// The text a reaction is displayed as, custom emojis are shown by name
+pub fn reaction_emoji(reaction_type: &ReactionType) -> Option<String> {
+    match reaction_type {
+        ReactionType::Custom { name, .. } => name.clone(),
+        ReactionType::Unicode(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Convert a parsed reaction into the form serenity sends
+///
+/// Shortcodes are looked up in the current guild first, then in every other cached guild
+pub fn reaction_type(
+    cache: &CacheRwLock,
+    guild_id: Option<GuildId>,
+    emoji: parsing::ReactionEmoji,
+) -> Result<ReactionType, String> {
+    use parsing::ReactionEmoji::*;
+    match emoji {
+        Unicode(unicode) => Ok(ReactionType::Unicode(unicode.to_string())),
+        Custom { animated, name, id } => Ok(ReactionType::Custom {
+            animated,
+            id: EmojiId(id),
+            name: Some(name.to_string()),
+        }),
+        Shortcode(name) => {
+            let current_guild = guild_id.and_then(|id| id.to_guild_cached(cache));
+            let other_guilds = cache.read().guilds.values().cloned().collect::<Vec<_>>();
+            current_guild
+                .into_iter()
+                .chain(other_guilds)
+                .find_map(|guild| {
+                    guild
+                        .read()
+                        .emojis
+                        .values()
+                        .find(|emoji| emoji.name == name)
+                        .map(|emoji| ReactionType::Custom {
+                            animated: emoji.animated,
+                            id: emoji.id,
+                            name: Some(emoji.name.clone()),
+                        })
+                })
+                .ok_or_else(|| format!("Unknown emoji :{}:", name))
+        },
+    }
+}
+
 /// Remove the guild id from global emojis
 pub fn clean_emojis(input: &str) -> String {
     let mut out = String::from(input);
@@ -481,3 +801,64 @@ pub fn clean_emojis(input: &str) -> String {
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_first_match() {
+        assert_eq!(
+            substitute("foo bar foo", "foo", "baz", ""),
+            Ok("baz bar foo".to_owned())
+        );
+    }
+
+    #[test]
+    fn substitute_flags() {
+        assert_eq!(
+            substitute("foo bar foo", "foo", "baz", "g"),
+            Ok("baz bar baz".to_owned())
+        );
+        assert_eq!(
+            substitute("foo bar foo", "foo", "baz", "2"),
+            Ok("foo bar baz".to_owned())
+        );
+        assert_eq!(substitute("a a a", "a", "b", "2g"), Ok("a b b".to_owned()));
+        assert_eq!(
+            substitute("Hello", "hello", "bye", "i"),
+            Ok("bye".to_owned())
+        );
+    }
+
+    #[test]
+    fn substitute_references() {
+        assert_eq!(
+            substitute("john smith", r"(\w+) (\w+)", r"\2 \1", ""),
+            Ok("smith john".to_owned())
+        );
+        assert_eq!(substitute("cat", "a", "[&]", ""), Ok("c[a]t".to_owned()));
+        assert_eq!(substitute("cost", "cost", "$5", ""), Ok("$5".to_owned()));
+    }
+
+    #[test]
+    fn substitute_errors() {
+        assert_eq!(
+            substitute("foo", "x", "y", ""),
+            Err("No match for 'x'".to_owned())
+        );
+        assert_eq!(
+            substitute("foo", "f", "g", "q"),
+            Err("Unknown substitution flag 'q'".to_owned())
+        );
+        assert!(substitute("foo", "f", "g", "0").is_err());
+        assert!(substitute("foo", "(", "g", "").is_err());
+    }
+
+    #[test]
+    fn sed_replacement_syntax() {
+        assert_eq!(sed_replacement(r"\1-&-$"), "${1}-${0}-$$");
+        assert_eq!(sed_replacement(r"\&\\\$"), r"&\$$");
+        assert_eq!(sed_replacement(r"end\"), r"end\");
+    }
+}
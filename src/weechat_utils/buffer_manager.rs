@@ -1,6 +1,6 @@
 use crate::weechat_utils::message_manager::MessageManager;
 use std::{cell::RefCell, collections::HashMap, sync::Arc};
-use weechat::Weechat;
+use weechat::{ConfigOption, Weechat};
 
 /// Manages all buffers for the plugin
 pub struct BufferManager {
@@ -57,4 +57,41 @@ impl BufferManager {
             Arc::clone(self.buffers.borrow().get(name).unwrap())
         }
     }
+
+    /// Evict the oldest messages across all buffers until the global message limit is met
+    ///
+    /// Like the limit of each buffer, it is raised by the messages paged in with older history
+    pub fn enforce_message_limit(&self) {
+        let limit = crate::upgrade_plugin(&self.weechat)
+            .config
+            .max_total_messages
+            .value() as usize;
+        if limit == 0 {
+            return;
+        }
+
+        let buffers = self.buffers.borrow();
+        let limit = limit + buffers.values().map(|buf| buf.paged_in()).sum::<usize>();
+        let mut total: usize = buffers.values().map(|buf| buf.message_count()).sum();
+        while total > limit {
+            let oldest = buffers
+                .values()
+                .filter_map(|buf| Some((buf.oldest_timestamp()?, buf)))
+                .min_by_key(|(timestamp, _)| *timestamp);
+            match oldest {
+                Some((_, buffer)) => buffer.evict_oldest(),
+                None => break,
+            }
+            total -= 1;
+        }
+    }
+
+    /// The name, message count and approximate memory usage of every buffer
+    pub fn stats(&self) -> Vec<(String, usize, usize)> {
+        self.buffers
+            .borrow()
+            .iter()
+            .map(|(name, buf)| (name.clone(), buf.message_count(), buf.memory_usage()))
+            .collect()
+    }
 }
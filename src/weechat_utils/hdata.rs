@@ -0,0 +1,245 @@
+//! Direct access to weechat's hdata, used to edit printed lines in place and inspect windows
+//!
+//! rust-weechat does not wrap hdata, so this goes through weechat-sys. Pointers returned by
+//! weechat are only valid until the buffer is next modified, so nothing here should be stored.
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_void,
+};
+use weechat::Weechat;
+use weechat_sys::{t_hdata, t_weechat_plugin};
+
+/// A line in a buffer, points to a weechat `line_data`
+pub struct LineData(*mut c_void);
+
+struct Hdata {
+    plugin: *mut t_weechat_plugin,
+}
+
+impl Hdata {
+    fn new(weechat: &Weechat) -> Hdata {
+        Hdata {
+            plugin: weechat.as_ptr(),
+        }
+    }
+
+    fn get(&self, name: &str) -> *mut t_hdata {
+        let name = CString::new(name).unwrap();
+        unsafe { (*self.plugin).hdata_get.unwrap()(self.plugin, name.as_ptr()) }
+    }
+
+    fn pointer(&self, hdata: *mut t_hdata, pointer: *mut c_void, name: &str) -> *mut c_void {
+        self.pointer_c(hdata, pointer, &CString::new(name).unwrap())
+    }
+
+    fn pointer_c(&self, hdata: *mut t_hdata, pointer: *mut c_void, name: &CStr) -> *mut c_void {
+        unsafe { (*self.plugin).hdata_pointer.unwrap()(hdata, pointer, name.as_ptr()) }
+    }
+
+    fn integer(&self, hdata: *mut t_hdata, pointer: *mut c_void, name: &str) -> i32 {
+        self.integer_c(hdata, pointer, &CString::new(name).unwrap())
+    }
+
+    fn integer_c(&self, hdata: *mut t_hdata, pointer: *mut c_void, name: &CStr) -> i32 {
+        unsafe { (*self.plugin).hdata_integer.unwrap()(hdata, pointer, name.as_ptr()) as i32 }
+    }
+
+    /// Borrow a string variable, only valid until the object is next modified
+    fn string_c<'a>(
+        &self,
+        hdata: *mut t_hdata,
+        pointer: *mut c_void,
+        name: &CStr,
+    ) -> Option<&'a CStr> {
+        unsafe {
+            let string = (*self.plugin).hdata_string.unwrap()(hdata, pointer, name.as_ptr());
+            if string.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(string))
+            }
+        }
+    }
+
+    /// Set string variables of `pointer`, returns false if weechat did not update all of them
+    fn update(&self, hdata: *mut t_hdata, pointer: *mut c_void, values: &[(&str, &str)]) -> bool {
+        let string_type = CString::new("string").unwrap();
+        let values = values
+            .iter()
+            .map(|(key, value)| (CString::new(*key), CString::new(*value)))
+            .filter_map(|(key, value)| Some((key.ok()?, value.ok()?)))
+            .collect::<Vec<_>>();
+        unsafe {
+            let hashtable = (*self.plugin).hashtable_new.unwrap()(
+                8,
+                string_type.as_ptr(),
+                string_type.as_ptr(),
+                None,
+                None,
+            );
+            if hashtable.is_null() {
+                return false;
+            }
+            for (key, value) in &values {
+                (*self.plugin).hashtable_set.unwrap()(
+                    hashtable,
+                    key.as_ptr() as *const c_void,
+                    value.as_ptr() as *const c_void,
+                );
+            }
+            let updated = (*self.plugin).hdata_update.unwrap()(hdata, pointer, hashtable);
+            (*self.plugin).hashtable_free.unwrap()(hashtable);
+            updated as usize == values.len()
+        }
+    }
+}
+
+/// All lines in a weecord buffer with the given tag, oldest first
+///
+/// The lines of a message are printed together, so the search starts at the end of the buffer,
+/// where recent messages are, and stops once it has passed the tagged lines.
+pub fn lines_with_tag(weechat: &Weechat, buffer_name: &str, tag: &str) -> Vec<LineData> {
+    let hdata = Hdata::new(weechat);
+    let plugin_name = CString::new("weecord").unwrap();
+    let buffer_name = match CString::new(buffer_name) {
+        Ok(name) => name,
+        Err(_) => return Vec::new(),
+    };
+
+    let buffer = unsafe {
+        (*hdata.plugin).buffer_search.unwrap()(
+            hdata.plugin,
+            plugin_name.as_ptr(),
+            buffer_name.as_ptr(),
+        )
+    };
+    if buffer.is_null() {
+        return Vec::new();
+    }
+
+    let buffer_hdata = hdata.get("buffer");
+    let lines_hdata = hdata.get("lines");
+    let line_hdata = hdata.get("line");
+    let line_data_hdata = hdata.get("line_data");
+
+    let own_lines = hdata.pointer(buffer_hdata, buffer as *mut c_void, "own_lines");
+    if own_lines.is_null() {
+        return Vec::new();
+    }
+
+    let data_name = CString::new("data").unwrap();
+    let prev_line_name = CString::new("prev_line").unwrap();
+    let tags_count_name = CString::new("tags_count").unwrap();
+    // "N|tags_array" for each tag index, created as lines with more tags are seen
+    let mut tag_names: Vec<CString> = Vec::new();
+
+    let mut found = Vec::new();
+    let mut line = hdata.pointer(lines_hdata, own_lines, "last_line");
+    while !line.is_null() {
+        let data = hdata.pointer_c(line_hdata, line, &data_name);
+        if !data.is_null() {
+            let tags_count = hdata
+                .integer_c(line_data_hdata, data, &tags_count_name)
+                .max(0) as usize;
+            while tag_names.len() < tags_count {
+                tag_names.push(CString::new(format!("{}|tags_array", tag_names.len())).unwrap());
+            }
+            let has_tag = tag_names[..tags_count].iter().any(|name| {
+                hdata
+                    .string_c(line_data_hdata, data, name)
+                    .map(CStr::to_bytes)
+                    == Some(tag.as_bytes())
+            });
+            if has_tag {
+                found.push(LineData(data));
+            } else if !found.is_empty() {
+                break;
+            }
+        }
+        line = hdata.pointer_c(line_hdata, line, &prev_line_name);
+    }
+    found.reverse();
+    found
+}
+
+/// Replace the prefix, message and optionally the tags of a printed line
+pub fn update_line(
+    weechat: &Weechat,
+    line: &LineData,
+    prefix: &str,
+    message: &str,
+    tags: Option<&str>,
+) -> bool {
+    let hdata = Hdata::new(weechat);
+    let line_data_hdata = hdata.get("line_data");
+    if line_data_hdata.is_null() {
+        return false;
+    }
+    let mut values = vec![("prefix", prefix), ("message", message)];
+    if let Some(tags) = tags {
+        values.push(("tags_array", tags));
+    }
+    hdata.update(line_data_hdata, line.0, &values)
+}
+
+/// The buffer shown in a window, if the window is scrolled up to the first line of that buffer
+pub fn scrolled_to_top(weechat: &Weechat, window: *mut c_void) -> Option<*mut c_void> {
+    let hdata = Hdata::new(weechat);
+    let window_hdata = hdata.get("window");
+    let buffer = hdata.pointer(window_hdata, window, "buffer");
+    let scroll = hdata.pointer(window_hdata, window, "scroll");
+    if buffer.is_null() || scroll.is_null() {
+        return None;
+    }
+
+    // start_line is null when the window is not scrolled
+    let start_line = hdata.pointer(hdata.get("window_scroll"), scroll, "start_line");
+    let lines = hdata.pointer(hdata.get("buffer"), buffer, "lines");
+    if start_line.is_null() || lines.is_null() {
+        return None;
+    }
+
+    let first_line = hdata.pointer(hdata.get("lines"), lines, "first_line");
+    if start_line == first_line {
+        Some(buffer)
+    } else {
+        None
+    }
+}
+
+/// The width of the chat area of the current window
+pub fn current_chat_width(weechat: &Weechat) -> Option<usize> {
+    let hdata = Hdata::new(weechat);
+    let window_hdata = hdata.get("window");
+    if window_hdata.is_null() {
+        return None;
+    }
+    let list = CString::new("gui_current_window").unwrap();
+    let window = unsafe { (*hdata.plugin).hdata_get_list.unwrap()(window_hdata, list.as_ptr()) };
+    if window.is_null() {
+        return None;
+    }
+    match hdata.integer(window_hdata, window, "win_chat_width") {
+        width if width > 0 => Some(width as usize),
+        _ => None,
+    }
+}
+
+/// The number of characters in a string once weechat color codes are removed
+pub fn visible_width(weechat: &Weechat, text: &str) -> usize {
+    let plugin = weechat.as_ptr();
+    let (text, replacement) = match (CString::new(text), CString::new("")) {
+        (Ok(text), Ok(replacement)) => (text, replacement),
+        _ => return 0,
+    };
+    unsafe {
+        let stripped = (*plugin).string_remove_color.unwrap()(text.as_ptr(), replacement.as_ptr());
+        if stripped.is_null() {
+            return 0;
+        }
+        let width = CStr::from_ptr(stripped).to_string_lossy().chars().count();
+        libc::free(stripped as *mut c_void);
+        width
+    }
+}
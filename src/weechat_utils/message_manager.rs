@@ -1,19 +1,69 @@
-use crate::utils::BufferExt;
+use crate::{utils::BufferExt, weechat_utils::hdata};
+use chrono::{DateTime, FixedOffset};
 use serenity::{
     cache::CacheRwLock,
     model::{
-        channel::{Message, MessageType},
+        channel::{Attachment, Embed, Message, MessageReaction, MessageType},
         id::{MessageId, UserId},
     },
 };
-use std::{cell::RefCell, ops::Deref, sync::Arc};
-use weechat::Buffer;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    mem::size_of,
+    ops::Deref,
+    sync::Arc,
+};
+use weechat::{Buffer, ConfigOption};
 
 /// MessageRenderer wraps a weechat buffer and facilitates editing the buffer and drawing the
 /// messages
 pub struct MessageManager {
     buffer: Buffer,
-    messages: Arc<RefCell<Vec<Message>>>,
+    messages: Arc<RefCell<VecDeque<Message>>>,
+    revealed_spoilers: RefCell<HashSet<MessageId>>,
+    deleted_messages: RefCell<HashSet<MessageId>>,
+    /// Earlier contents of messages that were edited while the buffer was open, oldest first
+    revisions: RefCell<HashMap<MessageId, Vec<Revision>>>,
+    /// The last message that was read when the history was loaded, a separator is shown after it
+    read_marker: RefCell<Option<MessageId>>,
+    /// Messages queued to be sent, shown after the stored messages until they are created
+    pending: RefCell<Vec<PendingLine>>,
+    /// How many messages were paged in with older history, the message limits are raised by
+    /// this much so they aren't evicted again by the next message
+    paged_in: Cell<usize>,
+    /// Summaries of bulk deletions, reprinted when the buffer is redrawn
+    bulk_deletions: RefCell<Vec<BulkDeletion>>,
+}
+
+/// A "N messages were deleted" line and the message it is shown after
+struct BulkDeletion {
+    after: Option<MessageId>,
+    count: usize,
+    timestamp: i64,
+}
+
+/// A queued message and the status shown next to it
+struct PendingLine {
+    nonce: String,
+    content: String,
+    status: String,
+}
+
+/// A version of a message's content and the time it was posted or edited at
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub content: String,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+impl Revision {
+    fn of(msg: &Message) -> Revision {
+        Revision {
+            content: msg.content.clone(),
+            timestamp: msg.edited_timestamp.unwrap_or(msg.timestamp),
+        }
+    }
 }
 
 impl MessageManager {
@@ -21,49 +71,490 @@ impl MessageManager {
     pub fn new(buffer: Buffer) -> MessageManager {
         MessageManager {
             buffer,
-            messages: Arc::new(RefCell::new(Vec::new())),
+            messages: Arc::new(RefCell::new(VecDeque::new())),
+            revealed_spoilers: RefCell::new(HashSet::new()),
+            deleted_messages: RefCell::new(HashSet::new()),
+            revisions: RefCell::new(HashMap::new()),
+            read_marker: RefCell::new(None),
+            pending: RefCell::new(Vec::new()),
+            paged_in: Cell::new(0),
+            bulk_deletions: RefCell::new(Vec::new()),
         }
     }
 
-    /// Format and print message to the buffer
-    fn print_msg(&self, cache: &CacheRwLock, msg: &Message, notify: bool) -> Vec<UserId> {
+    /// Whether spoilers should be shown for a message
+    fn spoilers_shown(&self, id: MessageId) -> bool {
+        let weechat = self.buffer.get_weechat();
+        crate::upgrade_plugin(&weechat).config.show_spoilers.value()
+            || self.revealed_spoilers.borrow().contains(&id)
+    }
+
+    /// Whether a message keeps its prefix after a message from the same author, weechat only
+    /// replaces the prefix with `weechat.look.prefix_same_nick` on lines with a `prefix_nick_`
+    /// tag
+    fn keeps_prefix(&self, previous: Option<&Message>, msg: &Message) -> bool {
+        let weechat = self.buffer.get_weechat();
+        let window = crate::upgrade_plugin(&weechat)
+            .config
+            .group_messages_window
+            .value();
+        // Without a window weechat groups every line following the same nick
+        if window == 0 {
+            return false;
+        }
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return true,
+        };
+
+        let is_chat = |msg: &Message| match msg.kind {
+            MessageType::Regular | MessageType::InlineReply => true,
+            _ => false,
+        };
+        // Replies always show their author, like the official client
+        let elapsed = msg.timestamp.timestamp() - previous.timestamp.timestamp();
+        !(previous.author.id == msg.author.id
+            && is_chat(previous)
+            && msg.kind == MessageType::Regular
+            && elapsed >= 0
+            && elapsed <= i64::from(window))
+    }
+
+    /// Render a message the way it should currently be displayed in this buffer
+    fn render(&self, cache: &CacheRwLock, msg: &Message) -> (String, String, Vec<UserId>) {
+        formatting_utils::render_msg(
+            cache,
+            &self.buffer.get_weechat(),
+            msg,
+            self.buffer.guild_id(),
+            self.spoilers_shown(msg.id),
+            self.deleted_messages.borrow().contains(&msg.id),
+        )
+    }
+
+    /// The tags for the lines of a message, `previous` is the message displayed before it
+    fn tags(
+        &self,
+        cache: &CacheRwLock,
+        msg: &Message,
+        previous: Option<&Message>,
+        notify: bool,
+    ) -> String {
         let weechat = self.buffer.get_weechat();
-        let maybe_guild = self.buffer.guild_id();
-        let (prefix, content, unknown_users) =
-            formatting_utils::render_msg(cache, &weechat, msg, maybe_guild);
+        let mut tags =
+            formatting_utils::msg_tags(cache, &weechat, msg, self.buffer.guild_id(), notify);
+        if self.keeps_prefix(previous, msg) {
+            tags.retain(|tag| !tag.starts_with("prefix_nick_"));
+        }
+        tags.join(",")
+    }
+
+    /// Format and print message to the buffer
+    fn print_msg(
+        &self,
+        cache: &CacheRwLock,
+        msg: &Message,
+        previous: Option<&Message>,
+        notify: bool,
+    ) -> Vec<UserId> {
+        if previous.is_some() && previous.map(|msg| msg.id) == *self.read_marker.borrow() {
+            self.print_read_marker(msg.timestamp.timestamp());
+        }
+        // Day change lines are drawn by weechat (look.day_change) from the line dates
+        let (prefix, content, unknown_users) = self.render(cache, msg);
         self.buffer.print_tags_dated(
             msg.timestamp.timestamp(),
-            &formatting_utils::msg_tags(cache, msg, notify).join(","),
+            &self.tags(cache, msg, previous, notify),
             &format!("{}\t{}", prefix, content),
         );
         unknown_users
     }
 
+    /// Rewrite the lines of a printed message in place, falling back to redrawing the whole
+    /// buffer when the message now takes a different number of lines
+    fn redraw_message(&self, cache: &CacheRwLock, msg: &Message) {
+        let (prefix, content, _) = self.render(cache, msg);
+        if !self.replace_lines(
+            &formatting_utils::msg_id_tag(msg.id),
+            &prefix,
+            &content,
+            None,
+        ) {
+            self.redraw_buffer(cache);
+        }
+    }
+
+    /// Replace the lines printed with `tag`, returns false if they couldn't all be replaced in
+    /// place because the new content takes a different number of lines
+    fn replace_lines(&self, tag: &str, prefix: &str, content: &str, tags: Option<&str>) -> bool {
+        let weechat = self.buffer.get_weechat();
+        // Weechat doesn't print a line for a trailing newline
+        let content = if content.ends_with('\n') {
+            &content[..content.len() - 1]
+        } else {
+            content
+        };
+        let new_lines = content.split('\n').collect::<Vec<_>>();
+        let old_lines = hdata::lines_with_tag(&weechat, &self.buffer.get_name(), tag);
+
+        !old_lines.is_empty()
+            && old_lines.len() == new_lines.len()
+            && old_lines
+                .iter()
+                .zip(new_lines)
+                .all(|(line, message)| hdata::update_line(&weechat, line, prefix, message, tags))
+    }
+
+    /// Redraw a stored message, used when something it is rendered with has changed
+    pub fn refresh_message(&self, cache: &CacheRwLock, id: MessageId) {
+        let msg = self
+            .messages
+            .borrow()
+            .iter()
+            .find(|msg| msg.id == id)
+            .cloned();
+        if let Some(msg) = msg {
+            self.redraw_message(cache, &msg);
+        }
+    }
+
     /// Clear the buffer and reprint all messages
     pub fn redraw_buffer(&self, cache: &CacheRwLock) {
         self.buffer.clear();
+        let bulk_deletions = self.bulk_deletions.borrow();
+        for deletion in bulk_deletions
+            .iter()
+            .filter(|deletion| deletion.after.is_none())
+        {
+            self.print_bulk_deletion(deletion);
+        }
+        let mut previous = None;
         for message in self.messages.borrow().iter() {
-            self.print_msg(cache, &message, false);
+            self.print_msg(cache, &message, previous, false);
+            for deletion in bulk_deletions
+                .iter()
+                .filter(|deletion| deletion.after == Some(message.id))
+            {
+                self.print_bulk_deletion(deletion);
+            }
+            previous = Some(message);
+        }
+        for pending in self.pending.borrow().iter() {
+            self.print_pending(pending);
+        }
+    }
+
+    /// Show a message that has been queued to be sent, until it is created
+    pub fn add_pending(&self, nonce: &str, content: &str) {
+        let pending = PendingLine {
+            nonce: nonce.to_owned(),
+            content: content.to_owned(),
+            status: "sending…".to_owned(),
+        };
+        self.print_pending(&pending);
+        self.pending.borrow_mut().push(pending);
+    }
+
+    /// Change the status shown next to a queued message
+    pub fn set_pending_status(&self, nonce: &str, status: &str) {
+        let mut pending = self.pending.borrow_mut();
+        if let Some(pending) = pending.iter_mut().find(|pending| pending.nonce == nonce) {
+            pending.status = status.to_owned();
+            let (prefix, content) = self.render_pending(pending);
+            self.replace_lines(
+                &formatting_utils::pending_tag(nonce),
+                &prefix,
+                &content,
+                None,
+            );
         }
     }
 
+    /// Stop tracking a queued message that won't be created, leaving it shown with `status`
+    pub fn resolve_pending(&self, nonce: &str, status: &str) {
+        self.set_pending_status(nonce, status);
+        self.pending
+            .borrow_mut()
+            .retain(|pending| pending.nonce != nonce);
+    }
+
+    fn render_pending(&self, pending: &PendingLine) -> (String, String) {
+        let weechat = self.buffer.get_weechat();
+        let nick = self
+            .buffer
+            .get_localvar("nick")
+            .map(|nick| nick.trim_start_matches('@').to_owned())
+            .unwrap_or_default();
+        let prefix = crate::utils::format_nick_color(&weechat, &nick);
+        let content = format!(
+            "{} {}({}){}",
+            pending.content,
+            weechat.color("8"),
+            pending.status,
+            weechat.color("reset")
+        );
+        (prefix, content)
+    }
+
+    fn print_pending(&self, pending: &PendingLine) {
+        let (prefix, content) = self.render_pending(pending);
+        self.buffer.print_tags_dated(
+            chrono::Utc::now().timestamp(),
+            &format!(
+                "{},notify_none,no_highlight,no_log",
+                formatting_utils::pending_tag(&pending.nonce)
+            ),
+            &format!("{}\t{}", prefix, content),
+        );
+    }
+
     /// Removes all content from the buffer
     pub fn clear(&self) {
         self.messages.borrow_mut().clear();
+        self.revealed_spoilers.borrow_mut().clear();
+        self.deleted_messages.borrow_mut().clear();
+        self.revisions.borrow_mut().clear();
+        self.read_marker.replace(None);
+        self.paged_in.set(0);
+        self.bulk_deletions.borrow_mut().clear();
         self.buffer.clear();
     }
 
+    /// Show a "new messages" separator after the given message, or remove it
+    pub fn set_read_marker(&self, cache: &CacheRwLock, id: Option<MessageId>) {
+        let changed = *self.read_marker.borrow() != id;
+        let shown = self.read_marker.borrow().map_or(false, |marker| {
+            self.messages.borrow().iter().any(|msg| msg.id == marker)
+        });
+        self.read_marker.replace(id);
+        // Removing the separator needs the lines to be reprinted
+        if changed && shown {
+            self.redraw_buffer(cache);
+        }
+    }
+
+    /// Print the separator between read and unread messages, dated like the first unread message
+    /// so weechat's day change lines are not affected by it
+    fn print_read_marker(&self, timestamp: i64) {
+        let weechat = self.buffer.get_weechat();
+        self.buffer.print_tags_dated(
+            timestamp,
+            "discord_read_marker,notify_none,no_highlight,no_log",
+            &format!(
+                "\t{}── new messages ──{}",
+                weechat.color("chat_read_marker"),
+                weechat.color("reset")
+            ),
+        );
+    }
+
+    /// The id of the nth most recent message (starting at 1)
+    ///
+    /// Like everywhere messages are numbered, deleted messages that are still shown aren't
+    /// counted, matching how Discord numbers them
+    pub fn nth_message_id(&self, n: usize) -> Option<MessageId> {
+        self.nth_message(n).map(|msg| msg.id)
+    }
+
+    /// A copy of the nth most recent message, not counting deleted messages
+    pub fn nth_message(&self, n: usize) -> Option<Message> {
+        self.find_nth_message(n, None).ok()
+    }
+
+    /// A copy of a stored message by id
+    pub fn message(&self, id: MessageId) -> Option<Message> {
+        self.messages
+            .borrow()
+            .iter()
+            .find(|msg| msg.id == id)
+            .cloned()
+    }
+
+    /// The nth most recent message (starting at 1) that hasn't been deleted, only counting
+    /// messages by `author` if given
+    ///
+    /// If there aren't enough stored messages, returns how many matched and the oldest stored
+    /// message, so the search can be continued from there
+    pub fn find_nth_message(
+        &self,
+        n: usize,
+        author: Option<UserId>,
+    ) -> Result<Message, (usize, Option<MessageId>)> {
+        let messages = self.messages.borrow();
+        let deleted = self.deleted_messages.borrow();
+        let matching = messages
+            .iter()
+            .rev()
+            .filter(|msg| !deleted.contains(&msg.id))
+            .filter(|msg| author.map_or(true, |author| msg.author.id == author));
+        let mut found = 0;
+        for msg in matching {
+            found += 1;
+            if found == n {
+                return Ok(msg.clone());
+            }
+        }
+        Err((found, messages.front().map(|msg| msg.id)))
+    }
+
+    /// Redraw the nth most recent message with its spoilers shown, returns false if there is no
+    /// such message
+    pub fn reveal_spoilers(&self, cache: &CacheRwLock, n: usize) -> bool {
+        let msg = match self.nth_message(n) {
+            Some(msg) => msg,
+            None => return false,
+        };
+        self.revealed_spoilers.borrow_mut().insert(msg.id);
+        self.redraw_message(cache, &msg);
+        true
+    }
+
     /// Add a message to the end of a buffer (chronologically)
     pub fn add_message(&self, cache: &CacheRwLock, msg: &Message, notify: bool) -> Vec<UserId> {
-        let mut msg = msg.clone();
-        if msg.referenced_message.is_some() && msg.message_reference.is_some() {
-            msg.kind = MessageType::InlineReply;
+        // Sent messages arrive both from the send queue and the gateway
+        if self
+            .messages
+            .borrow()
+            .iter()
+            .rev()
+            .any(|it| it.id == msg.id)
+        {
+            return Vec::new();
+        }
+        let mut msg = normalize_kind(msg);
+        let previous = self.messages.borrow().back().cloned();
+
+        let pending = msg.nonce.as_str().and_then(|nonce| {
+            let mut pending = self.pending.borrow_mut();
+            let index = pending.iter().position(|pending| pending.nonce == nonce)?;
+            Some(pending.remove(index))
+        });
+        let mut redraw = false;
+        let unknown_users = match pending {
+            // Replace the "sending" line with the created message
+            Some(pending) => {
+                let (prefix, content, unknown_users) = self.render(cache, &msg);
+                let tags = self.tags(cache, &msg, previous.as_ref(), false);
+                let tag = formatting_utils::pending_tag(&pending.nonce);
+                redraw = !self.replace_lines(&tag, &prefix, &content, Some(&tags));
+                unknown_users
+            },
+            None => self.print_msg(cache, &msg, previous.as_ref(), notify),
+        };
+        compact(&mut msg);
+        self.messages.borrow_mut().push_back(msg);
+        if redraw {
+            self.redraw_buffer(cache);
         }
-        let unknown_users = self.print_msg(cache, &msg, notify);
-        self.messages.borrow_mut().push(msg);
+
+        let weechat = self.buffer.get_weechat();
+        let weecord = crate::upgrade_plugin(&weechat);
+        let limit = weecord.config.max_buffer_messages.value() as usize;
+        while limit > 0 && self.message_count() > limit + self.paged_in() {
+            self.evict_oldest();
+        }
+        weecord.buffer_manager.enforce_message_limit();
+
+        unknown_users
+    }
+
+    /// Insert older messages (oldest first) before the stored ones and redraw the buffer
+    ///
+    /// The message limits are raised by the number of messages inserted, so paging back always
+    /// makes progress, they are reset when the history is reloaded
+    pub fn prepend_messages(&self, cache: &CacheRwLock, msgs: Vec<Message>) -> Vec<UserId> {
+        let weechat = self.buffer.get_weechat();
+        let mut unknown_users = Vec::new();
+        {
+            let mut messages = self.messages.borrow_mut();
+            for msg in msgs.iter().rev() {
+                let mut msg = normalize_kind(msg);
+                unknown_users.extend(
+                    formatting_utils::render_msg(
+                        cache,
+                        &weechat,
+                        &msg,
+                        self.buffer.guild_id(),
+                        false,
+                        false,
+                    )
+                    .2,
+                );
+                compact(&mut msg);
+                messages.push_front(msg);
+            }
+        }
+        self.paged_in.set(self.paged_in.get() + msgs.len());
+        self.redraw_buffer(cache);
         unknown_users
     }
 
+    /// The id of the oldest stored message
+    pub fn oldest_message_id(&self) -> Option<MessageId> {
+        self.messages.borrow().front().map(|msg| msg.id)
+    }
+
+    /// How many messages were paged in with older history since the history was loaded
+    pub fn paged_in(&self) -> usize {
+        self.paged_in.get()
+    }
+
+    /// Number of messages currently stored
+    pub fn message_count(&self) -> usize {
+        self.messages.borrow().len()
+    }
+
+    /// Approximate number of bytes used by the stored messages
+    pub fn memory_usage(&self) -> usize {
+        let revisions: usize = self
+            .revisions
+            .borrow()
+            .values()
+            .flatten()
+            .map(|revision| size_of::<Revision>() + revision.content.capacity())
+            .sum();
+        self.messages
+            .borrow()
+            .iter()
+            .map(approximate_size)
+            .sum::<usize>()
+            + revisions
+    }
+
+    /// Every known version of the nth most recent message (starting at 1), oldest first and
+    /// ending with the current content
+    pub fn revisions(&self, n: usize) -> Option<Vec<Revision>> {
+        let msg = self.nth_message(n)?;
+        let mut revisions = self
+            .revisions
+            .borrow()
+            .get(&msg.id)
+            .cloned()
+            .unwrap_or_default();
+        revisions.push(Revision::of(&msg));
+        Some(revisions)
+    }
+
+    /// Unix timestamp of the oldest stored message
+    pub fn oldest_timestamp(&self) -> Option<i64> {
+        self.messages
+            .borrow()
+            .front()
+            .map(|msg| msg.timestamp.timestamp())
+    }
+
+    /// Forget the oldest stored message, it stays in the buffer until the next redraw
+    pub fn evict_oldest(&self) {
+        if let Some(msg) = self.messages.borrow_mut().pop_front() {
+            self.revealed_spoilers.borrow_mut().remove(&msg.id);
+            self.deleted_messages.borrow_mut().remove(&msg.id);
+            self.revisions.borrow_mut().remove(&msg.id);
+            self.bulk_deletions
+                .borrow_mut()
+                .retain(|deletion| deletion.after.map_or(false, |after| after != msg.id));
+        }
+    }
+
     // Overwrite a previously printed message, has no effect if the message does not exist
     pub fn replace_message(
         &self,
@@ -71,44 +562,196 @@ impl MessageManager {
         id: &MessageId,
         msg: &Message,
     ) -> Vec<UserId> {
-        if let Some(old_msg) = self
+        let stored = self
             .messages
             .borrow_mut()
             .iter_mut()
             .find(|it| &it.id == id)
-        {
-            *old_msg = msg.clone();
+            .map(|old_msg| {
+                if old_msg.content != msg.content {
+                    self.revisions
+                        .borrow_mut()
+                        .entry(*id)
+                        .or_default()
+                        .push(Revision::of(old_msg));
+                }
+                *old_msg = normalize_kind(msg);
+                compact(old_msg);
+                old_msg.clone()
+            });
+        if let Some(stored) = stored {
+            self.redraw_message(cache, &stored);
         }
-        // Using hdata to edit the line might be more efficient Would still use redrawing as a fall
-        // back in the event that the edit has a different amount of lines
-        self.redraw_buffer(cache);
         let (_, _, unknown_users) = formatting_utils::render_msg(
             cache,
             &self.buffer.get_weechat(),
             msg,
             self.buffer.guild_id(),
+            false,
+            false,
         );
         unknown_users
     }
 
     /// Delete a previously printed message, has no effect if the message does not exist
+    ///
+    /// With `keep_deleted_messages` the message stays in the buffer, marked as deleted
     pub fn delete_message(&self, cache: &CacheRwLock, id: &MessageId) -> Vec<UserId> {
-        let index = self.messages.borrow().iter().position(|it| &it.id == id);
+        match self.remove_message(cache, id) {
+            Some((unknown_users, redraw)) => {
+                if redraw {
+                    self.redraw_buffer(cache);
+                }
+                unknown_users
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Delete several messages at once, redrawing once and printing a single summary line
+    pub fn delete_messages(&self, cache: &CacheRwLock, ids: &[MessageId]) -> Vec<UserId> {
         let mut unknown_users = Vec::new();
-        if let Some(index) = index {
-            let msg = self.messages.borrow_mut().remove(index);
-            unknown_users = formatting_utils::render_msg(
-                cache,
-                &self.buffer.get_weechat(),
-                &msg,
-                self.buffer.guild_id(),
-            )
-            .2;
+        let mut redraw = false;
+        let mut count = 0;
+        for id in ids {
+            if let Some((users, needs_redraw)) = self.remove_message(cache, id) {
+                unknown_users.extend(users);
+                redraw |= needs_redraw;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return unknown_users;
+        }
+
+        let deletion = BulkDeletion {
+            after: self.messages.borrow().back().map(|msg| msg.id),
+            count,
+            timestamp: chrono::Utc::now().timestamp(),
         };
-        // Performance, see comment above
-        self.redraw_buffer(cache);
+        if redraw {
+            self.bulk_deletions.borrow_mut().push(deletion);
+            self.redraw_buffer(cache);
+        } else {
+            self.print_bulk_deletion(&deletion);
+            self.bulk_deletions.borrow_mut().push(deletion);
+        }
         unknown_users
     }
+
+    fn print_bulk_deletion(&self, deletion: &BulkDeletion) {
+        let weechat = self.buffer.get_weechat();
+        self.buffer.print_tags_dated(
+            deletion.timestamp,
+            "notify_none,no_highlight,no_log",
+            &format!(
+                "{}\t{} messages were deleted",
+                weechat.get_prefix("quit"),
+                deletion.count
+            ),
+        );
+    }
+
+    /// Forget or mark a message as deleted, returns whether the buffer needs to be redrawn, or
+    /// None if the message isn't stored
+    fn remove_message(&self, cache: &CacheRwLock, id: &MessageId) -> Option<(Vec<UserId>, bool)> {
+        let weechat = self.buffer.get_weechat();
+        let keep_deleted = crate::upgrade_plugin(&weechat)
+            .config
+            .keep_deleted_messages
+            .value();
+
+        if keep_deleted {
+            let msg = self
+                .messages
+                .borrow()
+                .iter()
+                .find(|it| &it.id == id)
+                .cloned();
+            let msg = msg?;
+            self.deleted_messages.borrow_mut().insert(*id);
+            self.redraw_message(cache, &msg);
+            return Some((Vec::new(), false));
+        }
+
+        let index = self.messages.borrow().iter().position(|it| &it.id == id);
+        let index = index?;
+        let msg = self.messages.borrow_mut().remove(index)?;
+        // Keep bulk deletion lines where they were
+        let previous = index
+            .checked_sub(1)
+            .and_then(|index| self.messages.borrow().get(index).map(|msg| msg.id));
+        for deletion in self.bulk_deletions.borrow_mut().iter_mut() {
+            if deletion.after == Some(msg.id) {
+                deletion.after = previous;
+            }
+        }
+        let unknown_users = formatting_utils::render_msg(
+            cache,
+            &weechat,
+            &msg,
+            self.buffer.guild_id(),
+            false,
+            false,
+        )
+        .2;
+        // Lines can't be removed through hdata, so deleting always needs a full redraw
+        Some((unknown_users, true))
+    }
+}
+
+/// Drop the parts of a message that are not needed to render it again
+fn compact(msg: &mut Message) {
+    msg.mentions = Vec::new();
+    msg.mention_roles = Vec::new();
+    msg.nonce = serde_json::Value::Null;
+    msg.content.shrink_to_fit();
+    if let Some(referenced) = msg.referenced_message.as_mut() {
+        compact(referenced);
+    }
+}
+
+/// A rough estimate of the heap and inline size of a stored message
+fn approximate_size(msg: &Message) -> usize {
+    let attachments: usize = msg
+        .attachments
+        .iter()
+        .map(|a| size_of::<Attachment>() + a.filename.len() + a.url.len() + a.proxy_url.len())
+        .sum();
+    let embeds: usize = msg
+        .embeds
+        .iter()
+        .map(|embed| {
+            size_of::<Embed>()
+                + embed.title.as_ref().map_or(0, String::len)
+                + embed.description.as_ref().map_or(0, String::len)
+                + embed
+                    .fields
+                    .iter()
+                    .map(|field| field.name.len() + field.value.len())
+                    .sum::<usize>()
+        })
+        .sum();
+
+    size_of::<Message>()
+        + msg.content.capacity()
+        + msg.author.name.len()
+        + attachments
+        + embeds
+        + msg.reactions.len() * size_of::<MessageReaction>()
+        + msg
+            .referenced_message
+            .as_ref()
+            .map_or(0, |m| approximate_size(m))
+}
+
+/// Serenity doesn't know about inline replies, so detect them from the message reference
+fn normalize_kind(msg: &Message) -> Message {
+    let mut msg = msg.clone();
+    if msg.referenced_message.is_some() && msg.message_reference.is_some() {
+        msg.kind = MessageType::InlineReply;
+    }
+    msg
 }
 
 impl Deref for MessageManager {
@@ -121,20 +764,39 @@ impl Deref for MessageManager {
 
 mod formatting_utils {
     use crate::{
-        discord::formatting,
-        utils::{colorize_string, format_nick_color},
+        discord::{formatting, threads},
+        utils::{
+            colorize_string, format_nick_color, guess_mime_type, human_size, nick_color,
+            reaction_emoji, rgb_to_ansi,
+        },
+        weechat_utils::hdata,
     };
     use serenity::{
         cache::CacheRwLock,
         model::{
-            channel::{Channel, Message},
-            id::{GuildId, UserId},
+            channel::{Attachment, Channel, Embed, EmbedField, Message, MessageType},
+            id::{GuildId, MessageId, UserId},
         },
     };
     use std::str::FromStr;
     use weechat::{ConfigOption, Weechat};
 
-    pub fn msg_tags(cache: &CacheRwLock, msg: &Message, notify: bool) -> Vec<String> {
+    /// Discord marks attachments as spoilers by prefixing their file name
+    const SPOILER_PREFIX: &str = "SPOILER_";
+
+    /// Used to lay out embeds when the width of the current window is unknown
+    const DEFAULT_CHAT_WIDTH: usize = 80;
+
+    /// Discord shows at most three inline fields on a row
+    const MAX_INLINE_FIELDS: usize = 3;
+
+    pub fn msg_tags(
+        cache: &CacheRwLock,
+        weechat: &Weechat,
+        msg: &Message,
+        guild: Option<GuildId>,
+        notify: bool,
+    ) -> Vec<String> {
         let is_private = if let Some(channel) = msg.channel(cache) {
             if let Channel::Private(_) = channel {
                 true
@@ -160,7 +822,51 @@ mod formatting_utils {
             tags.push("notify_none");
         }
 
-        tags.into_iter().map(ToString::to_string).collect()
+        let mut tags = tags
+            .into_iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        tags.push(msg_id_tag(msg.id));
+        tags.push(format!("discord_author_{}", msg.author.id.0));
+
+        // Mirror the tags irc uses so filters and triggers can be shared
+        let nick = author_display_name(cache, msg, guild);
+        tags.push(format!("nick_{}", tag_safe(&nick)));
+        tags.push(format!(
+            "prefix_nick_{}",
+            nick_color(weechat, &nick).replace(',', ":")
+        ));
+        tags.push(format!(
+            "host_{}#{:04}",
+            tag_safe(&msg.author.name),
+            msg.author.discriminator
+        ));
+
+        if msg.author.bot {
+            tags.push("discord_bot".into());
+        }
+        if msg.webhook_id.is_some() {
+            tags.push("discord_webhook".into());
+        }
+        if msg.kind == MessageType::InlineReply {
+            tags.push("discord_reply".into());
+        }
+        tags
+    }
+
+    /// Tags are separated by commas and can't contain spaces
+    fn tag_safe(value: &str) -> String {
+        value.replace(',', "_").replace(' ', "_")
+    }
+
+    /// The tag the lines of a queued message are printed with
+    pub fn pending_tag(nonce: &str) -> String {
+        format!("discord_pending_{}", nonce)
+    }
+
+    /// The tag every line of a message is printed with, used to find the lines again
+    pub fn msg_id_tag(id: MessageId) -> String {
+        format!("discord_msgid_{}", id.0)
     }
 
     pub fn render_msg(
@@ -168,6 +874,8 @@ mod formatting_utils {
         weechat: &Weechat,
         msg: &Message,
         guild: Option<GuildId>,
+        show_spoilers: bool,
+        deleted: bool,
     ) -> (String, String, Vec<UserId>) {
         let opts = serenity::utils::ContentSafeOptions::new()
             .clean_here(false)
@@ -177,6 +885,11 @@ mod formatting_utils {
         let mut msg_content = serenity::utils::content_safe(&cache, &msg.content, &opts);
         msg_content = crate::utils::clean_emojis(&msg_content);
         let unknown_users = clean_users(cache, &mut msg_content, true, guild);
+        let deleted_content = if deleted {
+            Some(format_deleted(weechat, msg, &msg_content))
+        } else {
+            None
+        };
 
         if msg.edited_timestamp.is_some() {
             let edited_text = weechat.color("8").into_owned()
@@ -185,64 +898,41 @@ mod formatting_utils {
             msg_content.push_str(&edited_text);
         }
 
-        for attachement in &msg.attachments {
+        for (i, attachment) in msg.attachments.iter().enumerate() {
             if !msg_content.is_empty() {
                 msg_content.push('\n');
             }
-            msg_content.push_str(&attachement.proxy_url);
+            msg_content.push_str(&format_attachment(weechat, i + 1, attachment));
         }
 
+        let code_colors = crate::upgrade_plugin(weechat).config.code_colors();
+        let render_ctx = formatting::RenderContext {
+            reference_time: msg.timestamp.timestamp(),
+            show_spoilers,
+            code_colors: &code_colors,
+        };
+        msg_content = formatting::discord_to_weechat(weechat, &msg_content, render_ctx);
+
+        let chat_width = hdata::current_chat_width(weechat).unwrap_or(DEFAULT_CHAT_WIDTH);
         for embed in &msg.embeds {
             if !msg_content.is_empty() {
                 msg_content.push('\n');
             }
-            if let Some(ref author) = embed.author {
-                msg_content.push('▎');
+            msg_content.push_str(&format_embed(weechat, embed, render_ctx, chat_width));
+        }
+
+        if let Some(thread) = threads::started_from(msg.id) {
+            if thread.parent_id == msg.channel_id {
+                if !msg_content.is_empty() {
+                    msg_content.push('\n');
+                }
                 msg_content.push_str(&format!(
-                    "{}{}{}",
-                    weechat.color("bold"),
-                    author.name,
-                    weechat.color("reset"),
+                    "{}» thread {} ({} messages){}",
+                    weechat.color("8"),
+                    thread.name,
+                    thread.message_count,
+                    weechat.color("reset")
                 ));
-                if let Some(url) = &author.url {
-                    msg_content.push_str(&format!(" ({})", url));
-                }
-                msg_content.push('\n');
-            }
-            if let Some(ref title) = embed.title {
-                msg_content.push_str(
-                    &title
-                        .lines()
-                        .fold(String::new(), |acc, x| format!("{}▎{}\n", acc, x)),
-                );
-                msg_content.push('\n');
-            }
-            if let Some(ref description) = embed.description {
-                msg_content.push_str(
-                    &description
-                        .lines()
-                        .fold(String::new(), |acc, x| format!("{}▎{}\n", acc, x)),
-                );
-                msg_content.push('\n');
-            }
-            for field in &embed.fields {
-                msg_content.push_str(&field.name);
-                msg_content.push_str(
-                    &field
-                        .value
-                        .lines()
-                        .fold(String::new(), |acc, x| format!("{}▎{}\n", acc, x)),
-                );
-                msg_content.push('\n');
-            }
-            if let Some(ref footer) = embed.footer {
-                msg_content.push_str(
-                    &footer
-                        .text
-                        .lines()
-                        .fold(String::new(), |acc, x| format!("{}▎{}\n", acc, x)),
-                );
-                msg_content.push('\n');
             }
         }
 
@@ -250,16 +940,21 @@ mod formatting_utils {
             msg_content.push('\n');
         }
 
-        use serenity::model::channel::ReactionType;
         for reaction in &msg.reactions {
-            match &reaction.reaction_type {
-                ReactionType::Custom { name, .. } => name.clone(),
-                ReactionType::Unicode(s) => Some(s.clone()),
-                _ => None,
+            if let Some(emoji) = reaction_emoji(&reaction.reaction_type) {
+                // Highlight reactions added by the current user
+                if reaction.me {
+                    msg_content.push_str(&format!(
+                        "{}[{} {}]{} ",
+                        weechat.color("chat_highlight"),
+                        emoji,
+                        reaction.count,
+                        weechat.color("reset")
+                    ));
+                } else {
+                    msg_content.push_str(&format!("[{} {}] ", emoji, reaction.count));
+                }
             }
-            .map(|reaction_string| {
-                msg_content.push_str(&format!("[{} {}] ", reaction_string, reaction.count).as_str())
-            });
         }
 
         if msg.reactions.len() > 0 {
@@ -291,17 +986,17 @@ mod formatting_utils {
             }
         }
 
+        if let Some(content) = deleted_content {
+            return (prefix, content, unknown_users);
+        }
+
         use serenity::model::channel::MessageType::*;
         match msg.kind {
-            Regular => (
-                prefix,
-                formatting::discord_to_weechat(weechat, &msg_content),
-                unknown_users,
-            ),
+            Regular => (prefix, msg_content, unknown_users),
             InlineReply => match msg.referenced_message.as_ref() {
                 Some(ref_msg) => {
                     let (ref_prefix, ref_msg_content, mut ref_unknown_users) =
-                        render_msg(cache, weechat, &ref_msg, guild);
+                        render_msg(cache, weechat, &ref_msg, guild, show_spoilers, false);
                     ref_unknown_users.extend(unknown_users);
                     ref_unknown_users.sort();
                     ref_unknown_users.dedup();
@@ -357,6 +1052,241 @@ mod formatting_utils {
         }
     }
 
+    /// A numbered description of an attachment followed by its url on a separate line
+    ///
+    /// The result is escaped, as it is rendered as markdown along with the message content
+    fn format_attachment(weechat: &Weechat, number: usize, attachment: &Attachment) -> String {
+        let (filename, spoiler) = if attachment.filename.starts_with(SPOILER_PREFIX) {
+            (&attachment.filename[SPOILER_PREFIX.len()..], true)
+        } else {
+            (attachment.filename.as_str(), false)
+        };
+
+        let mut details = vec![human_size(attachment.size)];
+        if let Some(mime_type) = guess_mime_type(filename) {
+            details.push(mime_type.to_owned());
+        }
+        if let (Some(width), Some(height)) = (attachment.width, attachment.height) {
+            details.push(format!("{}×{}", width, height));
+        }
+        if spoiler {
+            details.push("spoiler".to_owned());
+        }
+
+        format!(
+            "{}[{}]{} {} {}({}){}\n{}",
+            weechat.color("8"),
+            number,
+            weechat.color("reset"),
+            parsing::escape_markdown(filename),
+            weechat.color("8"),
+            details.join(", "),
+            weechat.color("reset"),
+            parsing::escape_markdown(&attachment.proxy_url)
+        )
+    }
+
+    /// Render every part of an embed, each line starts with a gutter in the embed's color
+    fn format_embed(
+        weechat: &Weechat,
+        embed: &Embed,
+        ctx: formatting::RenderContext,
+        chat_width: usize,
+    ) -> String {
+        let bold = weechat.color("bold");
+        let dim = weechat.color("8");
+        let reset = weechat.color("reset");
+        let mut lines = Vec::new();
+
+        if let Some(name) = embed.provider.as_ref().and_then(|p| p.name.as_ref()) {
+            lines.push(format!("{}{}{}", dim, name, reset));
+        }
+        if let Some(ref author) = embed.author {
+            let mut line = format!("{}{}{}", bold, author.name, reset);
+            if let Some(ref url) = author.url {
+                line.push_str(&format!(" ({})", url));
+            }
+            lines.push(line);
+        }
+        if let Some(ref title) = embed.title {
+            let mut line = format!("{}{}{}", bold, title, reset);
+            if let Some(ref url) = embed.url {
+                line.push_str(&format!(" ({})", url));
+            }
+            lines.push(line);
+        } else if let Some(ref url) = embed.url {
+            lines.push(url.clone());
+        }
+        if let Some(ref description) = embed.description {
+            let description = formatting::discord_to_weechat(weechat, description, ctx);
+            lines.extend(description.lines().map(str::to_owned));
+        }
+
+        // The gutter takes two columns
+        let fields_width = chat_width.saturating_sub(2);
+        let mut fields = embed.fields.iter().peekable();
+        while let Some(field) = fields.next() {
+            let mut row = vec![field];
+            while field.inline && row.len() < MAX_INLINE_FIELDS {
+                match fields.peek() {
+                    Some(next) if next.inline => row.push(fields.next().unwrap()),
+                    _ => break,
+                }
+            }
+            lines.extend(format_fields(weechat, &row, ctx, fields_width));
+        }
+
+        for (label, media) in &[
+            (
+                "image",
+                embed.image.as_ref().map(|i| (&i.url, i.width, i.height)),
+            ),
+            (
+                "thumbnail",
+                embed
+                    .thumbnail
+                    .as_ref()
+                    .map(|t| (&t.url, t.width, t.height)),
+            ),
+            (
+                "video",
+                embed.video.as_ref().map(|v| (&v.url, v.width, v.height)),
+            ),
+        ] {
+            if let Some((url, width, height)) = media {
+                let size = if *width > 0 && *height > 0 {
+                    format!(" ({}×{})", width, height)
+                } else {
+                    String::new()
+                };
+                lines.push(format!("{}{}{}:{} {}", dim, label, size, reset, url));
+            }
+        }
+
+        let timestamp = embed
+            .timestamp
+            .as_ref()
+            .and_then(|timestamp| chrono::DateTime::parse_from_rfc3339(timestamp).ok())
+            .map(|timestamp| {
+                timestamp
+                    .with_timezone(&chrono::Local)
+                    .format("%-d %B %Y %H:%M")
+                    .to_string()
+            });
+        let footer = embed.footer.as_ref().map(|footer| footer.text.clone());
+        let footer = match (footer, timestamp) {
+            (Some(footer), Some(timestamp)) => Some(format!("{} • {}", footer, timestamp)),
+            (footer, timestamp) => footer.or(timestamp),
+        };
+        if let Some(footer) = footer {
+            lines.extend(
+                footer
+                    .lines()
+                    .map(|line| format!("{}{}{}", dim, line, reset)),
+            );
+        }
+
+        let gutter = if embed.colour.0 == 0 {
+            format!("{}▎{}", dim, reset)
+        } else {
+            format!(
+                "{}▎{}",
+                weechat.color(&rgb_to_ansi(embed.colour).to_string()),
+                reset
+            )
+        };
+        lines
+            .iter()
+            .map(|line| format!("{}{}", gutter, line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Lay out a row of fields side by side, or one after another if they don't fit in `width`
+    fn format_fields(
+        weechat: &Weechat,
+        fields: &[&EmbedField],
+        ctx: formatting::RenderContext,
+        width: usize,
+    ) -> Vec<String> {
+        const COLUMN_GAP: usize = 3;
+
+        let columns = fields
+            .iter()
+            .map(|field| {
+                let mut column = vec![format!(
+                    "{}{}{}",
+                    weechat.color("bold"),
+                    field.name,
+                    weechat.color("reset")
+                )];
+                let value = formatting::discord_to_weechat(weechat, &field.value, ctx);
+                column.extend(value.lines().map(str::to_owned));
+                column
+            })
+            .collect::<Vec<_>>();
+
+        if columns.len() == 1 {
+            return columns.into_iter().next().unwrap();
+        }
+
+        let widths = columns
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|line| hdata::visible_width(weechat, line))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let total = widths.iter().sum::<usize>() + COLUMN_GAP * (columns.len() - 1);
+        if total > width {
+            return columns.into_iter().flatten().collect();
+        }
+
+        let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+        (0..height)
+            .map(|row| {
+                let mut line = String::new();
+                for (i, column) in columns.iter().enumerate() {
+                    let cell = column.get(row).map_or("", String::as_str);
+                    line.push_str(cell);
+                    if i + 1 < columns.len() {
+                        let padding = widths[i] - hdata::visible_width(weechat, cell);
+                        line.push_str(&" ".repeat(padding + COLUMN_GAP));
+                    }
+                }
+                line.trim_end().to_owned()
+            })
+            .collect()
+    }
+
+    /// The unformatted, dimmed content of a deleted message followed by a marker
+    fn format_deleted(weechat: &Weechat, msg: &Message, content: &str) -> String {
+        let dim = weechat.color("8");
+        let reset = weechat.color("reset");
+        let mut lines = content
+            .lines()
+            .map(ToString::to_string)
+            .chain(msg.attachments.iter().map(|a| a.proxy_url.clone()))
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        let last = lines.len() - 1;
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let marker = if i == last { " (deleted)" } else { "" };
+                format!("{}{}{}{}", dim, line, marker, reset)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     pub fn author_display_name(
         cache: &CacheRwLock,
         msg: &Message,
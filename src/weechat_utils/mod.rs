@@ -1,4 +1,5 @@
 mod buffer_manager;
 pub use buffer_manager::BufferManager;
+pub mod hdata;
 mod message_manager;
 pub use message_manager::MessageManager;